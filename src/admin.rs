@@ -0,0 +1,285 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use sqlx::SqlitePool;
+use teloxide::{
+    prelude::*,
+    types::{BotCommandScope, InlineKeyboardButton, InlineKeyboardMarkup, Recipient},
+    utils::command::BotCommands,
+};
+
+use afisha_bot::{
+    broadcast::BroadcastFilter,
+    category::{self, Category, Rubric},
+    config::Config,
+    db::{
+        clear_rubrics, count_users_by_category, get_admins, get_all_users, get_user,
+        insert_broadcast, User,
+    },
+    directory::{self, Directory, SharedDirectory},
+};
+
+use crate::{user_summary, Command, HandlerResult, MyDialogue, State};
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "Команды администратора:")]
+pub enum AdminCommand {
+    #[command(description = "Статистика по пользователям.")]
+    Stats,
+    #[command(description = "Параметры пользователя: /user <tg_id>.")]
+    User { tg_id: String },
+    #[command(description = "Рассылка: /broadcast [city=…] [category=…] [lang=…] <текст>.")]
+    Broadcast { text: String },
+    #[command(description = "Перезагрузить справочники городов и категорий.")]
+    Reload,
+}
+
+pub const BROADCAST_CONFIRM: &str = "broadcast:confirm";
+pub const BROADCAST_CANCEL: &str = "broadcast:cancel";
+
+/// Admins come from `admin_ids` in the config and the `admins` table.
+pub async fn admin_ids(pool: &SqlitePool, config: &Config) -> Vec<ChatId> {
+    let mut ids: Vec<ChatId> = config.admin_ids.iter().copied().map(ChatId).collect();
+    match get_admins(pool).await {
+        Ok(admins) => ids.extend(admins.into_iter().map(ChatId)),
+        Err(error) => log::error!("Failed to load admins: {error}"),
+    }
+    ids.sort_by_key(|id| id.0);
+    ids.dedup();
+    ids
+}
+
+pub async fn is_admin(pool: &SqlitePool, config: &Config, chat_id: ChatId) -> bool {
+    admin_ids(pool, config).await.contains(&chat_id)
+}
+
+/// Admin commands are only advertised in admins' own chats.
+pub async fn set_commands(bot: &Bot, pool: &SqlitePool, config: &Config) {
+    if let Err(error) = bot.set_my_commands(Command::bot_commands()).await {
+        log::warn!("Failed to set bot commands: {error}");
+    }
+    let commands: Vec<_> = Command::bot_commands()
+        .into_iter()
+        .chain(AdminCommand::bot_commands())
+        .collect();
+    for chat_id in admin_ids(pool, config).await {
+        let result = bot
+            .set_my_commands(commands.clone())
+            .scope(BotCommandScope::Chat {
+                chat_id: Recipient::Id(chat_id),
+            })
+            .await;
+        if let Err(error) = result {
+            log::warn!("Failed to set admin commands for {chat_id}: {error}");
+        }
+    }
+}
+
+/// `rubrics` of the users' cities name the categories.
+fn format_stats(
+    users: &[User],
+    categories: &[(Category, i64)],
+    rubrics: &[Rubric],
+    timezone: Tz,
+) -> String {
+    let today = Utc::now().with_timezone(&timezone).date_naive();
+    let mut cities: BTreeMap<&str, usize> = BTreeMap::new();
+    for user in users {
+        *cities.entry(user.city.as_str()).or_default() += 1;
+    }
+    let active = users.iter().filter(|user| user.active).count();
+    let blocked = users
+        .iter()
+        .filter(|user| user.blocked_at.is_some())
+        .count();
+    let paused = users
+        .iter()
+        .filter(|user| user.paused || user.paused_until.is_some_and(|until| until > Utc::now()))
+        .count();
+    let sent_today = users
+        .iter()
+        .filter(|user| {
+            user.last_digest_at
+                .is_some_and(|sent_at| sent_at.with_timezone(&timezone).date_naive() == today)
+        })
+        .count();
+
+    let mut text = format!(
+        "Пользователей: {}\nАктивных: {active}\nНеактивных: {}\nЗаблокировали бота: {blocked}\nНа паузе: {paused}\nДайджестов сегодня: {sent_today}\n\nПо городам:",
+        users.len(),
+        users.len() - active,
+    );
+    for (city, count) in cities {
+        text = format!("{text}\n{city}: {count}");
+    }
+    text = format!("{text}\n\nПо категориям:");
+    for (category, count) in categories {
+        let name = category::names(std::slice::from_ref(category), rubrics);
+        text = format!("{text}\n{name}: {count}");
+    }
+    text
+}
+
+pub async fn cmd_stats(
+    bot: Bot,
+    msg: Message,
+    pool: SqlitePool,
+    config: Arc<Config>,
+) -> HandlerResult {
+    let users = get_all_users(&pool).await.unwrap_or_default();
+    let categories = count_users_by_category(&pool).await.unwrap_or_default();
+    let cities: BTreeSet<&str> = users.iter().map(|user| user.city.as_str()).collect();
+    let mut rubrics = Vec::new();
+    for city in cities {
+        rubrics.extend(directory::cached_categories(&pool, city).await);
+    }
+    bot.send_message(
+        msg.chat.id,
+        format_stats(&users, &categories, &rubrics, config.timezone),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn cmd_user(
+    bot: Bot,
+    msg: Message,
+    tg_id: String,
+    pool: SqlitePool,
+    config: Arc<Config>,
+) -> HandlerResult {
+    let Ok(tg_id) = tg_id.trim().parse() else {
+        bot.send_message(msg.chat.id, "Использование: /user <tg_id>")
+            .await?;
+        return Ok(());
+    };
+    let Some(user) = get_user(&pool, tg_id).await else {
+        bot.send_message(msg.chat.id, "Пользователь не найден.")
+            .await?;
+        return Ok(());
+    };
+    let rubrics = directory::cached_categories(&pool, &user.city).await;
+    let format_time = |time: Option<chrono::DateTime<Utc>>| match time {
+        Some(time) => time
+            .with_timezone(&config.timezone)
+            .format("%d.%m.%Y %H:%M")
+            .to_string(),
+        None => "—".into(),
+    };
+    let text = format!(
+        "{}\nАктивен: {}\nНа паузе: {}\nЗаблокировал бота: {}\nПоследний дайджест: {}",
+        user_summary(&user, config.timezone, &rubrics),
+        if user.active { "да" } else { "нет" },
+        if user.paused { "да" } else { "нет" },
+        format_time(user.blocked_at),
+        format_time(user.last_digest_at),
+    );
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
+
+pub async fn cmd_broadcast(
+    bot: Bot,
+    msg: Message,
+    text: String,
+    dialogue: MyDialogue,
+    pool: SqlitePool,
+) -> HandlerResult {
+    let (filter, text) = BroadcastFilter::parse(&text);
+    if text.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "Использование: /broadcast [city=moscow] [category=concert] [lang=ru] <текст>",
+        )
+        .await?;
+        return Ok(());
+    }
+    let recipients = get_all_users(&pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter(|user| filter.matches(user))
+        .count();
+    let keyboard = InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("Отправить", BROADCAST_CONFIRM),
+        InlineKeyboardButton::callback("Отмена", BROADCAST_CANCEL),
+    ]]);
+    bot.send_message(
+        msg.chat.id,
+        format!("Разослать это сообщение {recipients} пользователям ({filter})?\n\n{text}"),
+    )
+    .reply_markup(keyboard)
+    .await?;
+    dialogue
+        .update(State::ConfirmBroadcast { text, filter })
+        .await?;
+    Ok(())
+}
+
+/// Queues the confirmed broadcast; `broadcast::run_worker` delivers it and keeps the
+/// confirmation message updated with progress.
+pub async fn receive_broadcast_confirmation(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (text, filter): (String, BroadcastFilter),
+    q: CallbackQuery,
+    pool: SqlitePool,
+) -> HandlerResult {
+    bot.answer_callback_query(q.id).await?;
+    let Some(message) = q.message else {
+        return Ok(());
+    };
+    dialogue.exit().await?;
+    if q.data.as_deref() != Some(BROADCAST_CONFIRM) {
+        bot.edit_message_text(message.chat.id, message.id, "Рассылка отменена.")
+            .await?;
+        return Ok(());
+    }
+    let total = get_all_users(&pool)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter(|user| filter.matches(user))
+        .count();
+    let id = insert_broadcast(
+        &pool,
+        message.chat.id.0,
+        message.id.0,
+        &text,
+        &filter,
+        total as i64,
+    )
+    .await?;
+    bot.edit_message_text(
+        message.chat.id,
+        message.id,
+        format!("Рассылка #{id} поставлена в очередь ({total} получателей)."),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn cmd_reload(
+    bot: Bot,
+    msg: Message,
+    pool: SqlitePool,
+    directory: SharedDirectory,
+    config: Arc<Config>,
+) -> HandlerResult {
+    let fresh = Directory::load(&config).await;
+    // Categories are fetched again per city the next time someone picks them.
+    if let Err(error) = clear_rubrics(&pool).await {
+        log::error!("Failed to clear cached categories: {error}");
+    }
+    let text = format!(
+        "Справочники обновлены. Городов: {}, категории обновятся при следующем выборе.",
+        fresh.cities.len(),
+    );
+    *directory.write().unwrap() = fresh;
+    bot.send_message(msg.chat.id, text).await?;
+    Ok(())
+}
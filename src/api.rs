@@ -1,25 +1,147 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
 
-use serde::{Deserialize, Serialize};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-const AFISHA_API_ROOT: &str = "https://afisha.yandex.ru/api/";
+use crate::{
+    category::{Category, Rubric},
+    config::Config,
+    metrics::metrics,
+};
 
-pub const CATEGORIES: [&str; 7] = [
-    "cinema", "concert", "theatre", "art", "standup", "show", "quest",
-];
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum EventsInterval {
+    Today,
+    Tomorrow,
+    Weekend,
+    Week,
+    Month,
+    Range { from: NaiveDate, to: NaiveDate },
+}
+
+impl EventsInterval {
+    /// Start date and number of days to request from Afisha, counted from `today`.
+    pub fn date_period(&self, today: NaiveDate) -> (NaiveDate, u32) {
+        match self {
+            EventsInterval::Today => (today, 1),
+            EventsInterval::Tomorrow => (today + Duration::days(1), 1),
+            EventsInterval::Weekend => match today.weekday() {
+                Weekday::Sat => (today, 2),
+                Weekday::Sun => (today, 1),
+                weekday => {
+                    let until_saturday = 5 - weekday.num_days_from_monday();
+                    (today + Duration::days(until_saturday.into()), 2)
+                }
+            },
+            EventsInterval::Week => (today, 7),
+            EventsInterval::Month => {
+                let next_month = if today.month() == 12 {
+                    NaiveDate::from_ymd_opt(today.year() + 1, 1, 1)
+                } else {
+                    NaiveDate::from_ymd_opt(today.year(), today.month() + 1, 1)
+                }
+                .unwrap();
+                (today, (next_month - today).num_days() as u32)
+            }
+            EventsInterval::Range { from, to } => {
+                let start = (*from).max(today);
+                let period = (*to - start).num_days() + 1;
+                (start, period.max(0) as u32)
+            }
+        }
+    }
+}
+
+impl fmt::Display for EventsInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventsInterval::Today => write!(f, "сегодня"),
+            EventsInterval::Tomorrow => write!(f, "завтра"),
+            EventsInterval::Weekend => write!(f, "эти выходные"),
+            EventsInterval::Week => write!(f, "ближайшие 7 дней"),
+            EventsInterval::Month => write!(f, "этот месяц"),
+            EventsInterval::Range { from, to } => write!(
+                f,
+                "с {} по {}",
+                from.format("%d.%m.%Y"),
+                to.format("%d.%m.%Y")
+            ),
+        }
+    }
+}
+
+/// Which events a user wants by Afisha's age rating.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum AgeFilter {
+    #[default]
+    Any,
+    /// Only events rated for this age or younger, e.g. 6 keeps 0+ and 6+.
+    UpTo(u8),
+    /// Everything except events made for children.
+    NoKids,
+}
+
+impl AgeFilter {
+    /// The "family mode" preset: events a young child can go to.
+    pub const FAMILY: AgeFilter = AgeFilter::UpTo(6);
+    /// Ratings Afisha uses.
+    pub const RATINGS: [u8; 5] = [0, 6, 12, 16, 18];
+
+    /// Whether `event` passes. Events without a rating are dropped by `UpTo`,
+    /// since nobody checked them for children.
+    pub fn allows(&self, event: &Event) -> bool {
+        match self {
+            AgeFilter::Any => true,
+            AgeFilter::UpTo(age) => event.age_rating().is_some_and(|rating| rating <= *age),
+            AgeFilter::NoKids => !event.is_for_kids(),
+        }
+    }
+}
+
+impl fmt::Display for AgeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgeFilter::Any => write!(f, "любой"),
+            AgeFilter::UpTo(age) if *self == AgeFilter::FAMILY => {
+                write!(f, "семейный режим (до {age}+)")
+            }
+            AgeFilter::UpTo(age) => write!(f, "до {age}+"),
+            AgeFilter::NoKids => write!(f, "без детских"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct City {
+    pub id: String,
+    pub name: String,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Resp {
-    data: Vec<Elements>,
+pub struct CitiesResp {
+    data: Vec<City>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Resp2 {
-    paging: Smth,
+pub struct RubricsResp {
+    data: Vec<Rubric>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Smth {
+pub struct Resp {
+    data: Vec<Elements>,
+    /// Missing from saved fixtures, which hold a single page.
+    #[serde(default)]
+    paging: Paging,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Paging {
     total: i64,
 }
 
@@ -28,52 +150,407 @@ pub struct Elements {
     event: Event,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Event {
     pub id: String,
     pub url: String,
-    pub title: String
+    pub title: String,
+    #[serde(default)]
+    pub tickets: Vec<Ticket>,
+    /// Age restriction as Afisha shows it, e.g. `12+`.
+    #[serde(default, rename = "contentRating")]
+    pub content_rating: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tag {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ticket {
+    #[serde(default)]
+    pub price: Option<Price>,
+}
+
+const KIDS_TAG: &str = "kids";
+
+/// Ticket price range in kopecks, as Afisha sends it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Price {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl Event {
+    /// Cheapest ticket in rubles, if Afisha lists any prices.
+    pub fn min_price(&self) -> Option<u32> {
+        self.tickets
+            .iter()
+            .filter_map(|ticket| ticket.price.as_ref()?.min)
+            .min()
+            .map(|kopecks| u32::try_from(kopecks / 100).unwrap_or(u32::MAX))
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.min_price() == Some(0)
+    }
+
+    /// Minimum age from `content_rating`: 12 for `12+`.
+    pub fn age_rating(&self) -> Option<u8> {
+        self.content_rating
+            .as_deref()?
+            .trim()
+            .trim_end_matches('+')
+            .parse()
+            .ok()
+    }
+
+    /// Afisha tags children's shows with `kids`.
+    pub fn is_for_kids(&self) -> bool {
+        self.tags.iter().any(|tag| tag.code == KIDS_TAG)
+    }
+}
+
+/// Events in any of `categories`, merged in category order. An event listed under
+/// several of them is returned once.
+#[tracing::instrument(skip_all, fields(%city, ?categories, ?interval, events))]
+pub async fn get_events(
+    config: &Config,
+    city: String,
+    categories: Vec<Category>,
+    interval: &EventsInterval,
+    today: NaiveDate,
+) -> Result<Vec<Event>, reqwest::Error> {
+    let (date, period) = interval.date_period(today);
+    if period == 0 {
+        return Ok(Vec::new());
+    }
+    let mut seen = HashSet::new();
+    let mut events = Vec::new();
+    for category in &categories {
+        for event in get_category_events(config, &city, category, date, period).await? {
+            if seen.insert(event.id.clone()) {
+                events.push(event);
+            }
+        }
+    }
+    tracing::Span::current().record("events", events.len());
+    Ok(events)
 }
 
-pub async fn get_events(city: String, categories: Vec<String>, period: u32) -> Vec<Event> {
-    let resp = reqwest::get(format!(
-        "{}{}{}{}{}{}{}{}",
-        AFISHA_API_ROOT,
-        "events/actual?",
-        "period=",
+#[tracing::instrument(
+    skip_all,
+    fields(category = category.as_str(), cached, pages, events)
+)]
+async fn get_category_events(
+    config: &Config,
+    city: &str,
+    category: &Category,
+    date: NaiveDate,
+    period: u32,
+) -> Result<Vec<Event>, reqwest::Error> {
+    let key = (
+        city.to_string(),
+        category.as_str().to_string(),
+        date,
         period,
-        "&city=",
-        city,
-        "&tag=",
-        categories[0]
-    ))
-    .await
-    .unwrap();
-    let json = resp.json::<Resp2>().await.unwrap();
-    let mut total = json.paging.total;
+    );
+    if let Some(events) = cached_events(&key, config.events_cache_ttl) {
+        tracing::Span::current()
+            .record("cached", true)
+            .record("events", events.len());
+        return Ok(events);
+    }
+    let date = date.format("%Y-%m-%d");
+    let limit = config.api_page_size;
     let mut offset = 0;
-    let step = 12;
     let mut events = Vec::new();
-    while total > 0 {
-        let resp = reqwest::get(format!(
-            "{}{}{}{}{}{}{}{}",
-            AFISHA_API_ROOT,
-            "events/actual?",
-            "city=",
-            city,
-            "&tag=",
-            categories[0],
-            "&period=",
-            period
-        ))
-        .await
-        .unwrap();
-        let json = resp.json::<Resp>().await.unwrap();
-        offset += step;
-        total -= step;
-        for event in json.data {
-            events.push(event.event);
+    let mut pages = 0;
+    loop {
+        let json: Resp = fetch(
+            "events",
+            format!(
+                "{}events/actual?city={}&tag={}&date={}&period={}&offset={}&limit={}",
+                config.api_root,
+                city,
+                category.as_str(),
+                date,
+                period,
+                offset,
+                limit
+            ),
+        )
+        .await?;
+        pages += 1;
+        let received = json.data.len();
+        events.extend(json.data.into_iter().map(|element| element.event));
+        offset += i64::from(limit);
+        // An empty page ends the loop even if `total` is off, so it can't spin.
+        if received == 0 || offset >= json.paging.total {
+            break;
         }
     }
+    tracing::Span::current()
+        .record("cached", false)
+        .record("pages", pages)
+        .record("events", events.len());
+    events_cache()
+        .lock()
+        .unwrap()
+        .insert(key, (Instant::now(), events.clone()));
+    Ok(events)
+}
+
+type EventsKey = (String, String, NaiveDate, u32);
+type EventsCache = Mutex<HashMap<EventsKey, (Instant, Vec<Event>)>>;
+
+fn events_cache() -> &'static EventsCache {
+    static CACHE: OnceLock<EventsCache> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Users with the same city and category share one Afisha request per `ttl`.
+fn cached_events(key: &EventsKey, ttl: std::time::Duration) -> Option<Vec<Event>> {
+    let mut cache = events_cache().lock().unwrap();
+    cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < ttl);
+    let events = cache.get(key).map(|(_, events)| events.clone());
+    let result = if events.is_some() { "hit" } else { "miss" };
+    metrics().cache.with_label_values(&[result]).inc();
     events
 }
+
+#[tracing::instrument(name = "afisha", skip(url), fields(%url, status))]
+async fn fetch<T: DeserializeOwned>(endpoint: &str, url: String) -> Result<T, reqwest::Error> {
+    let started = Instant::now();
+    let result = async {
+        let resp = reqwest::get(&url).await?;
+        tracing::Span::current().record("status", resp.status().as_u16());
+        resp.error_for_status()?.json().await
+    }
+    .await;
+    metrics().observe_afisha(endpoint, started, &result);
+    if let Err(error) = &result {
+        tracing::warn!(%error, "Afisha request failed");
+    }
+    result
+}
+
+/// Events from a saved `events/actual` response, for offline rendering.
+pub fn events_from_json(json: &str) -> Result<Vec<Event>, serde_json::Error> {
+    let resp: Resp = serde_json::from_str(json)?;
+    Ok(resp.data.into_iter().map(|element| element.event).collect())
+}
+
+pub async fn get_cities(config: &Config) -> Result<Vec<City>, reqwest::Error> {
+    let json: CitiesResp = fetch("cities", format!("{}{}", config.api_root, "cities")).await?;
+    Ok(json.data)
+}
+
+/// Event rubrics Afisha offers in `city`, in its own order.
+pub async fn get_rubrics(config: &Config, city: &str) -> Result<Vec<Rubric>, reqwest::Error> {
+    let json: RubricsResp = fetch(
+        "rubrics",
+        format!("{}{}{}", config.api_root, "rubrics?city=", city),
+    )
+    .await?;
+    Ok(json.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32, month: u32, year: i32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn weekend_periods() {
+        let monday = date(12, 10, 2026);
+        // Weekdays look ahead to Saturday; on Sunday only Sunday is left.
+        let cases = [
+            (0, 5, 2),
+            (1, 5, 2),
+            (2, 5, 2),
+            (3, 5, 2),
+            (4, 5, 2),
+            (5, 5, 2),
+            (6, 6, 1),
+        ];
+        for (today, start, period) in cases {
+            let today = monday + Duration::days(today);
+            let start = monday + Duration::days(start);
+            assert_eq!(
+                EventsInterval::Weekend.date_period(today),
+                (start, period),
+                "{}",
+                today.weekday()
+            );
+        }
+    }
+
+    #[test]
+    fn date_periods() {
+        let new_year_eve = date(31, 12, 2026);
+        assert_eq!(
+            EventsInterval::Tomorrow.date_period(new_year_eve),
+            (date(1, 1, 2027), 1)
+        );
+        assert_eq!(
+            EventsInterval::Week.date_period(new_year_eve),
+            (new_year_eve, 7)
+        );
+        // The rest of the month, today included.
+        let months = [
+            (new_year_eve, 1),
+            (date(1, 2, 2028), 29),
+            (date(14, 10, 2026), 18),
+        ];
+        for (today, period) in months {
+            assert_eq!(EventsInterval::Month.date_period(today), (today, period));
+        }
+    }
+
+    #[test]
+    fn range_periods() {
+        let today = date(14, 10, 2026);
+        let range = |from, to| EventsInterval::Range { from, to };
+        let cases = [
+            // Not started yet.
+            (
+                range(date(20, 10, 2026), date(25, 10, 2026)),
+                date(20, 10, 2026),
+                6,
+            ),
+            // Started: what is left from today.
+            (range(date(10, 10, 2026), date(20, 10, 2026)), today, 7),
+            (range(date(10, 10, 2026), today), today, 1),
+            // Over.
+            (range(date(1, 10, 2026), date(13, 10, 2026)), today, 0),
+        ];
+        for (interval, start, period) in cases {
+            assert_eq!(interval.date_period(today), (start, period), "{interval:?}");
+        }
+    }
+
+    /// A trimmed `events/actual` page with the ratings and tags Afisha uses.
+    const FIXTURE: &str = r#"{"data": [
+        {"event": {"id": "toddlers", "url": "toddlers", "title": "Toddlers",
+            "contentRating": "0+", "tags": [{"code": "kids"}, {"code": "theatre"}]}},
+        {"event": {"id": "cartoon", "url": "cartoon", "title": "Cartoon",
+            "contentRating": "6+", "tags": [{"code": "cinema"}]}},
+        {"event": {"id": "drama", "url": "drama", "title": "Drama",
+            "contentRating": "12+", "tags": [{"code": "theatre"}]}},
+        {"event": {"id": "padded", "url": "padded", "title": "Padded",
+            "contentRating": " 16+ "}},
+        {"event": {"id": "unrated", "url": "unrated", "title": "Unrated",
+            "tags": [{"code": "concert"}]}}
+    ]}"#;
+
+    fn allowed(filter: AgeFilter) -> Vec<String> {
+        events_from_json(FIXTURE)
+            .unwrap()
+            .into_iter()
+            .filter(|event| filter.allows(event))
+            .map(|event| event.id)
+            .collect()
+    }
+
+    #[test]
+    fn reads_age_ratings() {
+        let ratings: Vec<_> = events_from_json(FIXTURE)
+            .unwrap()
+            .iter()
+            .map(|event| (event.age_rating(), event.is_for_kids()))
+            .collect();
+        assert_eq!(
+            ratings,
+            [
+                (Some(0), true),
+                (Some(6), false),
+                (Some(12), false),
+                (Some(16), false),
+                (None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_age() {
+        assert_eq!(
+            allowed(AgeFilter::Any),
+            ["toddlers", "cartoon", "drama", "padded", "unrated"]
+        );
+        assert_eq!(allowed(AgeFilter::UpTo(6)), ["toddlers", "cartoon"]);
+        assert_eq!(
+            allowed(AgeFilter::UpTo(16)),
+            ["toddlers", "cartoon", "drama", "padded"]
+        );
+        assert_eq!(
+            allowed(AgeFilter::NoKids),
+            ["cartoon", "drama", "padded", "unrated"]
+        );
+    }
+
+    /// Afisha listing `a` and `b` as cinema and `b` and `c` as concerts.
+    fn afisha() -> String {
+        use axum::{extract::Query, routing::get, Json, Router};
+
+        let app = Router::new().route(
+            "/events/actual",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                let ids: &[&str] = match query["tag"].as_str() {
+                    "cinema" => &["a", "b"],
+                    "concert" => &["b", "c"],
+                    _ => &[],
+                };
+                let data: Vec<_> = ids
+                    .iter()
+                    .map(|id| serde_json::json!({ "event": { "id": id, "url": "", "title": id } }))
+                    .collect();
+                Json(serde_json::json!({ "data": data, "paging": { "total": ids.len() } }))
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        format!("http://{address}/")
+    }
+
+    #[tokio::test]
+    async fn merges_categories() {
+        let config = Config {
+            api_root: afisha(),
+            ..Config::default()
+        };
+        let events = |categories| {
+            // A city of its own keeps other tests out of the shared cache.
+            get_events(
+                &config,
+                "merge-test".to_string(),
+                categories,
+                &EventsInterval::Today,
+                date(14, 10, 2026),
+            )
+        };
+        let ids = |events: Vec<Event>| -> Vec<String> {
+            events.into_iter().map(|event| event.id).collect()
+        };
+
+        let merged = events(vec![Category::Cinema, Category::Concert])
+            .await
+            .unwrap();
+        assert_eq!(ids(merged), ["a", "b", "c"]);
+        let merged = events(vec![Category::Concert, Category::Cinema])
+            .await
+            .unwrap();
+        assert_eq!(ids(merged), ["b", "c", "a"]);
+        assert!(events(Vec::new()).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use afisha_bot::{
+    api::events_from_json,
+    category::{builtin_rubrics, Category},
+    config::Config,
+    db::{
+        backup, get_all_users, get_rubrics, get_user, get_users_in_category, init_db, set_active,
+        update_user, upsert_user, vacuum, User, UserFilter,
+    },
+    digest,
+    parse::{parse_categories, parse_interval, parse_time},
+};
+use chrono::{NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+
+/// Offline maintenance for the afisha bot database.
+#[derive(Parser)]
+#[command(name = "afisha-admin")]
+struct Cli {
+    /// Config file, `afisha.toml` by default.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Path to the SQLite database, overrides `db_url` from the config.
+    #[arg(long)]
+    db: Option<String>,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Inspect and edit users.
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Create missing tables and columns.
+    Migrate,
+    /// Print the digest a user would get, rendered from a saved Afisha response.
+    Digest {
+        tg_id: u64,
+        /// JSON body of an `events/actual` response.
+        #[arg(long)]
+        fixture: PathBuf,
+        /// Digest date, today by default.
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// Write all users as JSON to stdout or a file.
+    Export {
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Insert or update users, settings and delivery state, from a JSON file produced
+    /// by `export`.
+    Import { input: PathBuf },
+    /// Rebuild the database file to reclaim space.
+    Vacuum,
+    /// Copy the database to `path` without stopping the bot.
+    Backup { path: String },
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    List {
+        /// Only users subscribed to the category with this Afisha id.
+        #[arg(long)]
+        category: Option<String>,
+    },
+    Show {
+        tg_id: u64,
+    },
+    Edit {
+        tg_id: u64,
+        #[arg(long)]
+        city: Option<String>,
+        /// Comma-separated category list.
+        #[arg(long)]
+        categories: Option<String>,
+        #[arg(long)]
+        time: Option<String>,
+        #[arg(long)]
+        interval: Option<String>,
+        #[arg(long)]
+        active: Option<bool>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
+    let db = cli.db.unwrap_or_else(|| config.db_url.clone());
+
+    if !Sqlite::database_exists(&db).await.unwrap_or(false) {
+        if !matches!(cli.command, Commands::Migrate | Commands::Import { .. }) {
+            return Err(format!("database {db} does not exist").into());
+        }
+        Sqlite::create_database(&db).await?;
+    }
+    let pool = SqlitePool::connect(&db).await?;
+    init_db(&pool).await;
+
+    match cli.command {
+        Commands::Users(command) => users(&pool, command).await?,
+        Commands::Migrate => println!("Database {db} is up to date."),
+        Commands::Digest {
+            tg_id,
+            fixture,
+            date,
+        } => {
+            let user = find_user(&pool, tg_id).await?;
+            let events = digest::filter(&user, events_from_json(&fs::read_to_string(fixture)?)?);
+            let date =
+                date.unwrap_or_else(|| Utc::now().with_timezone(&config.timezone).date_naive());
+            let (start, period) = user.events_interval.date_period(date);
+            println!(
+                "Digest for {tg_id} on {date}: {} from {start} for {period} days",
+                user.city
+            );
+            for message in digest::render(&events, config.page_size) {
+                println!("---{message}");
+            }
+        }
+        Commands::Export { output } => {
+            let users = get_all_users(&pool).await.unwrap_or_default();
+            let json = serde_json::to_string_pretty(&users)?;
+            match output {
+                Some(path) => fs::write(path, json)?,
+                None => println!("{json}"),
+            }
+        }
+        Commands::Import { input } => {
+            let users: Vec<User> = serde_json::from_str(&fs::read_to_string(input)?)?;
+            let count = users.len();
+            for user in &users {
+                upsert_user(&pool, user).await?;
+            }
+            println!("Imported {count} users.");
+        }
+        Commands::Vacuum => vacuum(&pool).await?,
+        Commands::Backup { path } => {
+            backup(&pool, &path).await?;
+            println!("Backup written to {path}.");
+        }
+    }
+    Ok(())
+}
+
+async fn find_user(pool: &SqlitePool, tg_id: u64) -> Result<User, Box<dyn Error>> {
+    get_user(pool, tg_id)
+        .await
+        .ok_or_else(|| format!("user {tg_id} not found").into())
+}
+
+async fn users(pool: &SqlitePool, command: UsersCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        UsersCommand::List { category } => {
+            let users = match category {
+                Some(category) => {
+                    get_users_in_category(pool, &Category::from_id(category.trim())).await?
+                }
+                None => get_all_users(pool).await.unwrap_or_default(),
+            };
+            for user in users {
+                let categories: Vec<&str> = user.categories.iter().map(Category::as_str).collect();
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    user.tg_id,
+                    user.city,
+                    categories.join(","),
+                    user.notification_time.format("%H:%M"),
+                    user.events_interval,
+                    if user.active { "active" } else { "inactive" },
+                );
+            }
+        }
+        UsersCommand::Show { tg_id } => {
+            let user = find_user(pool, tg_id).await?;
+            println!("{}", serde_json::to_string_pretty(&user)?);
+        }
+        UsersCommand::Edit {
+            tg_id,
+            city,
+            categories,
+            time,
+            interval,
+            active,
+        } => {
+            let user = find_user(pool, tg_id).await?;
+            // No network here: accept what is cached for the city, or the built-in list.
+            let known = get_rubrics(pool, city.as_deref().unwrap_or(&user.city))
+                .await?
+                .map_or_else(builtin_rubrics, |(rubrics, _)| rubrics);
+            let categories = categories
+                .as_deref()
+                .map(|categories| parse_categories(categories, &known))
+                .transpose()?;
+            let notification_time = time.as_deref().map(parse_time).transpose()?;
+            let events_interval = interval.as_deref().map(parse_interval).transpose()?;
+            update_user(
+                pool,
+                UserFilter {
+                    id: None,
+                    tg_id: None,
+                    city,
+                    categories,
+                    notification_time,
+                    events_interval,
+                },
+                tg_id,
+            )
+            .await?;
+            if let Some(active) = active {
+                set_active(pool, tg_id, active).await?;
+            }
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&find_user(pool, tg_id).await?)?
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,433 @@
+use std::fmt;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use teloxide::{prelude::*, types::MessageId, RequestError};
+use tokio::time::{sleep, Duration};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    db::{
+        deactivate_user, finish_broadcast, get_running_broadcasts, get_users_after,
+        update_broadcast_progress, Broadcast, User,
+    },
+    is_chat_unreachable,
+    rate_limit::RateLimiter,
+};
+
+const BATCH_SIZE: i64 = 50;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Which users a broadcast goes to. Empty fields match everyone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BroadcastFilter {
+    pub city: Option<String>,
+    pub category: Option<String>,
+    pub language: Option<String>,
+}
+
+impl BroadcastFilter {
+    /// Splits leading `city=`, `category=` and `lang=` options off the broadcast text.
+    pub fn parse(text: &str) -> (Self, String) {
+        let mut filter = Self::default();
+        let mut rest = text.trim_start();
+        while let Some((token, tail)) = rest.split_once(char::is_whitespace) {
+            match token.split_once('=') {
+                Some(("city", value)) => filter.city = Some(value.to_string()),
+                Some(("category", value)) => filter.category = Some(value.to_string()),
+                Some(("lang", value)) => filter.language = Some(value.to_string()),
+                _ => break,
+            }
+            rest = tail.trim_start();
+        }
+        (filter, rest.trim().to_string())
+    }
+
+    /// Whether `user` should actually receive the broadcast.
+    pub fn matches(&self, user: &User) -> bool {
+        self.targets(user) && is_reachable(user)
+    }
+
+    fn targets(&self, user: &User) -> bool {
+        if let Some(city) = &self.city {
+            if !user.city.eq_ignore_ascii_case(city) {
+                return false;
+            }
+        }
+        if let Some(category) = &self.category {
+            if !user
+                .categories
+                .iter()
+                .any(|subscribed| subscribed.as_str() == category)
+            {
+                return false;
+            }
+        }
+        if let Some(language) = &self.language {
+            if user.language.as_deref() != Some(language.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Display for BroadcastFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            self.city.as_ref().map(|city| format!("город {city}")),
+            self.category
+                .as_ref()
+                .map(|category| format!("категория {category}")),
+            self.language
+                .as_ref()
+                .map(|language| format!("язык {language}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if parts.is_empty() {
+            write!(f, "все пользователи")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+fn is_reachable(user: &User) -> bool {
+    user.active && user.blocked_at.is_none()
+}
+
+fn progress_text(broadcast: &Broadcast, done: bool) -> String {
+    format!(
+        "Рассылка #{} ({}){}\nОтправлено: {} из {}\nОшибок: {}\nПропущено: {}",
+        broadcast.id,
+        broadcast.filter,
+        if done { " завершена" } else { "" },
+        broadcast.sent,
+        broadcast.total,
+        broadcast.failed,
+        broadcast.skipped,
+    )
+}
+
+/// Delivers pending broadcasts, sharing the send budget of `limiter` with digests.
+/// Progress is stored after every message, so jobs left running by a restart or a
+/// crash continue from their cursor without repeating anyone.
+pub async fn run_worker(
+    bot: Bot,
+    pool: SqlitePool,
+    limiter: RateLimiter,
+    shutdown: CancellationToken,
+) {
+    while !shutdown.is_cancelled() {
+        match get_running_broadcasts(&pool).await {
+            Ok(broadcasts) => {
+                for broadcast in broadcasts {
+                    if let Err(error) = process(&bot, &pool, &limiter, broadcast, &shutdown).await {
+                        log::error!("Broadcast failed: {error}");
+                    }
+                }
+            }
+            Err(error) => log::error!("Failed to load broadcasts: {error}"),
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+async fn process(
+    bot: &Bot,
+    pool: &SqlitePool,
+    limiter: &RateLimiter,
+    mut broadcast: Broadcast,
+    shutdown: &CancellationToken,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    loop {
+        let users = get_users_after(pool, broadcast.cursor, BATCH_SIZE).await?;
+        if users.is_empty() {
+            break;
+        }
+        for user in users {
+            if broadcast.filter.targets(&user) {
+                if !is_reachable(&user) {
+                    broadcast.skipped += 1;
+                } else if deliver(bot, pool, limiter, &mut broadcast, &user, shutdown).await? {
+                    // Stored before the next send, so a crash never repeats this one.
+                    broadcast.cursor = user.id;
+                    update_broadcast_progress(pool, &broadcast).await?;
+                } else {
+                    // Interrupted before sending, so `user` is resumed with the rest.
+                    update_broadcast_progress(pool, &broadcast).await?;
+                    return Ok(());
+                }
+            }
+            broadcast.cursor = user.id;
+            if shutdown.is_cancelled() {
+                update_broadcast_progress(pool, &broadcast).await?;
+                return Ok(());
+            }
+        }
+        update_broadcast_progress(pool, &broadcast).await?;
+        report(bot, &broadcast, false).await;
+    }
+    finish_broadcast(pool, broadcast.id).await?;
+    report(bot, &broadcast, true).await;
+    Ok(())
+}
+
+/// Sends `broadcast` to `user`, waiting out flood control. Returns `false` if
+/// `shutdown` came before the message could be sent.
+async fn deliver(
+    bot: &Bot,
+    pool: &SqlitePool,
+    limiter: &RateLimiter,
+    broadcast: &mut Broadcast,
+    user: &User,
+    shutdown: &CancellationToken,
+) -> Result<bool, sqlx::Error> {
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return Ok(false),
+            _ = limiter.acquire() => {}
+        }
+        match bot
+            .send_message(ChatId(user.tg_id as i64), &broadcast.message)
+            .await
+        {
+            Ok(_) => broadcast.sent += 1,
+            Err(RequestError::RetryAfter(duration)) => {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return Ok(false),
+                    _ = sleep(duration) => continue,
+                }
+            }
+            Err(error) => {
+                broadcast.failed += 1;
+                if is_chat_unreachable(&error) {
+                    deactivate_user(pool, user.tg_id, Utc::now()).await?;
+                } else {
+                    log::warn!(
+                        "Broadcast #{} to {} failed: {error}",
+                        broadcast.id,
+                        user.tg_id
+                    );
+                }
+            }
+        }
+        return Ok(true);
+    }
+}
+
+async fn report(bot: &Bot, broadcast: &Broadcast, done: bool) {
+    let result = bot
+        .edit_message_text(
+            ChatId(broadcast.admin_chat_id),
+            MessageId(broadcast.status_message_id),
+            progress_text(broadcast, done),
+        )
+        .await;
+    // Editing fails with "message is not modified" when a batch changed nothing.
+    if let Err(error) = result {
+        log::debug!(
+            "Failed to update broadcast #{} status: {error}",
+            broadcast.id
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::{routing::post, Json, Router};
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::{
+        category::Category,
+        db::{init_db, insert_broadcast, upsert_user},
+    };
+
+    #[test]
+    fn parses_leading_options() {
+        let (filter, text) = BroadcastFilter::parse("city=moscow lang=ru Привет всем");
+        assert_eq!(
+            filter,
+            BroadcastFilter {
+                city: Some("moscow".into()),
+                category: None,
+                language: Some("ru".into()),
+            }
+        );
+        assert_eq!(text, "Привет всем");
+
+        let (filter, text) = BroadcastFilter::parse("  category=concert\n\nСегодня концерт ");
+        assert_eq!(filter.category.as_deref(), Some("concert"));
+        assert_eq!(text, "Сегодня концерт");
+    }
+
+    #[test]
+    fn keeps_text_that_looks_like_options() {
+        for text in ["2+2=4 и это всё", "цена=500 рублей", "Привет city=moscow"]
+        {
+            assert_eq!(
+                BroadcastFilter::parse(text),
+                (BroadcastFilter::default(), text.to_string()),
+                "{text}"
+            );
+        }
+        // Options end at the first word that is not one.
+        let (filter, text) = BroadcastFilter::parse("lang=ru цена=500 рублей");
+        assert_eq!(filter.language.as_deref(), Some("ru"));
+        assert_eq!(text, "цена=500 рублей");
+    }
+
+    #[test]
+    fn targets_matching_users() {
+        let user = User {
+            city: "Moscow".into(),
+            categories: vec![Category::Concert, Category::Other("quiz".into())],
+            language: Some("ru".into()),
+            ..User::default()
+        };
+        let targets = |options: &str| {
+            BroadcastFilter::parse(&format!("{options} text"))
+                .0
+                .targets(&user)
+        };
+
+        assert!(targets(""));
+        assert!(targets("city=moscow"));
+        assert!(targets("city=MOSCOW category=concert lang=ru"));
+        assert!(targets("category=quiz"));
+        assert!(!targets("city=spb"));
+        assert!(!targets("category=cinema"));
+        assert!(!targets("lang=en"));
+        assert!(!targets("city=moscow lang=en"));
+        assert!(!BroadcastFilter::parse("lang=ru text")
+            .0
+            .targets(&User::default()));
+    }
+
+    /// A Bot API that accepts the first `accepted` messages and then puts every
+    /// request under an hour of flood control, notifying `flooded` each time.
+    fn flooded_telegram(accepted: usize, flooded: Arc<Notify>) -> Bot {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/:token/:method",
+            post(move || async move {
+                if requests.fetch_add(1, Ordering::SeqCst) < accepted {
+                    return Json(serde_json::json!({
+                        "ok": true,
+                        "result": {
+                            "message_id": 1,
+                            "date": 0,
+                            "chat": { "id": 1, "type": "private", "first_name": "Test" },
+                            "text": "hi",
+                        },
+                    }));
+                }
+                flooded.notify_one();
+                Json(serde_json::json!({
+                    "ok": false,
+                    "error_code": 429,
+                    "description": "Too Many Requests: retry after 3600",
+                    "parameters": { "retry_after": 3600 },
+                }))
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        Bot::new("1:test").set_api_url(format!("http://{address}").parse().unwrap())
+    }
+
+    /// A pool with `users` subscribed and one broadcast to all of them.
+    async fn test_pool(users: &[u64]) -> (SqlitePool, Broadcast) {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_db(&pool).await;
+        for &tg_id in users {
+            let user = User {
+                tg_id,
+                ..User::default()
+            };
+            upsert_user(&pool, &user).await.unwrap();
+        }
+        let filter = BroadcastFilter::default();
+        insert_broadcast(&pool, 1, 1, "hi", &filter, users.len() as i64)
+            .await
+            .unwrap();
+        let broadcast = get_running_broadcasts(&pool).await.unwrap().remove(0);
+        (pool, broadcast)
+    }
+
+    #[tokio::test]
+    async fn shutdown_interrupts_flood_wait() {
+        let (pool, broadcast) = test_pool(&[42]).await;
+        let flooded = Arc::new(Notify::new());
+        let bot = flooded_telegram(0, flooded.clone());
+        let limiter = RateLimiter::per_second(1);
+        let shutdown = CancellationToken::new();
+
+        let processing = process(&bot, &pool, &limiter, broadcast, &shutdown);
+        let stop = async {
+            flooded.notified().await;
+            shutdown.cancel();
+        };
+        let (result, ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(processing, stop)
+        })
+        .await
+        .expect("shutdown waited for flood control");
+        result.unwrap();
+
+        // The user was not reached, so a restart sends to them again.
+        let broadcast = get_running_broadcasts(&pool).await.unwrap().remove(0);
+        assert_eq!(broadcast.cursor, 0);
+        assert_eq!(broadcast.sent + broadcast.failed, 0);
+    }
+
+    #[tokio::test]
+    async fn progress_is_stored_after_each_send() {
+        let (pool, broadcast) = test_pool(&[42, 43]).await;
+        let flooded = Arc::new(Notify::new());
+        let bot = flooded_telegram(1, flooded.clone());
+        let limiter = RateLimiter::per_second(100);
+        let shutdown = CancellationToken::new();
+
+        let processing = process(&bot, &pool, &limiter, broadcast, &shutdown);
+        let check = async {
+            // The second user is stuck behind flood control in the middle of the
+            // batch; a crash now must not send to the first one again.
+            flooded.notified().await;
+            let stored = get_running_broadcasts(&pool).await.unwrap().remove(0);
+            shutdown.cancel();
+            stored
+        };
+        let (result, stored) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(processing, check)
+        })
+        .await
+        .unwrap();
+        result.unwrap();
+
+        let first = get_users_after(&pool, 0, 1).await.unwrap().remove(0);
+        assert_eq!(first.tg_id, 42);
+        assert_eq!(stored.cursor, first.id);
+        assert_eq!(stored.sent, 1);
+    }
+}
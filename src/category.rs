@@ -0,0 +1,192 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Afisha event category. Stored and sent to the API by its id (`concert`), shown
+/// to users by its Russian name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub enum Category {
+    Cinema,
+    Concert,
+    Theatre,
+    Art,
+    Standup,
+    Show,
+    Quest,
+    /// A rubric from a city's catalogue that has no variant of its own.
+    Other(String),
+}
+
+impl Category {
+    pub const ALL: [Category; 7] = [
+        Category::Cinema,
+        Category::Concert,
+        Category::Theatre,
+        Category::Art,
+        Category::Standup,
+        Category::Show,
+        Category::Quest,
+    ];
+
+    /// The built-in category with this Afisha id, or [`Category::Other`].
+    pub fn from_id(id: &str) -> Self {
+        Category::ALL
+            .into_iter()
+            .find(|category| category.as_str() == id)
+            .unwrap_or_else(|| Category::Other(id.to_string()))
+    }
+
+    /// Afisha tag id.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Category::Cinema => "cinema",
+            Category::Concert => "concert",
+            Category::Theatre => "theatre",
+            Category::Art => "art",
+            Category::Standup => "standup",
+            Category::Show => "show",
+            Category::Quest => "quest",
+            Category::Other(id) => id,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Category::Cinema => "Кино",
+            Category::Concert => "Концерты",
+            Category::Theatre => "Театр",
+            Category::Art => "Выставки",
+            Category::Standup => "Стендап",
+            Category::Show => "Шоу",
+            Category::Quest => "Квесты",
+            Category::Other(id) => id,
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownCategory(pub String);
+
+impl fmt::Display for UnknownCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} не категория.", self.0)
+    }
+}
+
+impl std::error::Error for UnknownCategory {}
+
+/// Accepts either the id (`concert`) or the Russian name (`концерты`) of a built-in
+/// category.
+impl FromStr for Category {
+    type Err = UnknownCategory;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim().to_lowercase();
+        Category::ALL
+            .into_iter()
+            .find(|category| category.as_str() == text || category.name().to_lowercase() == text)
+            .ok_or(UnknownCategory(text))
+    }
+}
+
+impl From<Category> for String {
+    fn from(category: Category) -> Self {
+        category.as_str().to_string()
+    }
+}
+
+impl From<String> for Category {
+    fn from(id: String) -> Self {
+        Category::from_id(&id)
+    }
+}
+
+/// A category one city offers, with the name Afisha shows for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rubric {
+    #[serde(rename = "id")]
+    pub category: Category,
+    pub name: String,
+}
+
+impl Rubric {
+    /// Whether `text` is this rubric's id or name.
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.trim().to_lowercase();
+        self.category.as_str() == text
+            || self.name.to_lowercase() == text
+            || self.category.name().to_lowercase() == text
+    }
+}
+
+/// Used when Afisha can't be reached and nothing is cached for the city.
+pub fn builtin_rubrics() -> Vec<Rubric> {
+    Category::ALL
+        .into_iter()
+        .map(|category| Rubric {
+            name: category.name().to_string(),
+            category,
+        })
+        .collect()
+}
+
+/// Names of `categories` for messages and buttons, as `rubrics` of the user's city
+/// call them. Categories missing from `rubrics` fall back to [`Category::name`].
+pub fn names(categories: &[Category], rubrics: &[Rubric]) -> String {
+    categories
+        .iter()
+        .map(|category| {
+            rubrics
+                .iter()
+                .find(|rubric| rubric.category == *category)
+                .map_or(category.name(), |rubric| rubric.name.as_str())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Names of `rubrics` for the category picker.
+pub fn rubric_names(rubrics: &[Rubric]) -> String {
+    rubrics
+        .iter()
+        .map(|rubric| rubric.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_follow_city_rubrics() {
+        let rubrics = [
+            Rubric {
+                category: Category::Other("kids".into()),
+                name: "Детям".into(),
+            },
+            Rubric {
+                category: Category::Concert,
+                name: "Концерты и фестивали".into(),
+            },
+        ];
+        let categories = [
+            Category::Other("kids".into()),
+            Category::Concert,
+            Category::Cinema,
+            Category::Other("sport".into()),
+        ];
+        assert_eq!(
+            names(&categories, &rubrics),
+            "Детям, Концерты и фестивали, Кино, sport"
+        );
+        assert_eq!(names(&categories, &[]), "kids, Концерты, Кино, sport");
+    }
+}
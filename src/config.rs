@@ -0,0 +1,473 @@
+use std::{
+    env, fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono_tz::Tz;
+use reqwest::Url;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+pub const DEFAULT_PATH: &str = "afisha.toml";
+
+/// Runtime settings. Read from a TOML file, then overridden by environment variables.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Bot token. Only the bot itself needs it, so it is checked by [`Config::token`].
+    pub token: Option<String>,
+    pub db_url: String,
+    pub api_root: String,
+    pub admin_ids: Vec<i64>,
+    /// Timezone notification times are interpreted in.
+    pub timezone: Tz,
+    /// How many digests are sent at the same time.
+    pub concurrency: usize,
+    /// How often the scheduler checks for due digests.
+    pub tick: Duration,
+    /// How long shutdown waits for digests that are being sent.
+    pub shutdown_timeout: Duration,
+    /// Events per digest message.
+    pub page_size: usize,
+    /// Events requested per Afisha API page.
+    pub api_page_size: u32,
+    /// How long the city and category lists are kept before they are reloaded.
+    pub directory_ttl: Duration,
+    /// How long fetched events are reused for other users with the same settings.
+    pub events_cache_ttl: Duration,
+    /// `tracing_subscriber::EnvFilter` directives, e.g. `info,afisha_bot=debug`.
+    pub log_level: String,
+    pub log_format: LogFormat,
+    /// Public base URL Telegram sends updates to. Without it the bot uses long polling.
+    pub webhook_url: Option<Url>,
+    /// Local address the webhook server listens on.
+    pub webhook_address: SocketAddr,
+    /// Path of the webhook, both in the public URL and on the local server.
+    pub webhook_path: String,
+    /// Expected `X-Telegram-Bot-Api-Secret-Token`; generated on start if not set.
+    pub webhook_secret: Option<String>,
+    /// Address of the `/healthz`, `/readyz` and `/metrics` server.
+    pub metrics_address: SocketAddr,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            token: None,
+            db_url: "afisha.db".into(),
+            api_root: "https://afisha.yandex.ru/api/".into(),
+            admin_ids: Vec::new(),
+            timezone: chrono_tz::Europe::Moscow,
+            concurrency: 4,
+            tick: Duration::from_secs(60),
+            shutdown_timeout: Duration::from_secs(30),
+            page_size: 10,
+            api_page_size: 12,
+            directory_ttl: Duration::from_secs(24 * 60 * 60),
+            events_cache_ttl: Duration::from_secs(5 * 60),
+            log_level: "info".into(),
+            log_format: LogFormat::Text,
+            webhook_url: None,
+            webhook_address: ([0, 0, 0, 0], 8443).into(),
+            webhook_path: "/webhook".into(),
+            webhook_secret: None,
+            metrics_address: ([0, 0, 0, 0], 9090).into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log shipping.
+    Json,
+}
+
+/// Config file layout. Every key is optional and falls back to [`Config::default`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    token: Option<String>,
+    db_url: Option<String>,
+    api_root: Option<String>,
+    admin_ids: Option<Vec<i64>>,
+    timezone: Option<String>,
+    concurrency: Option<usize>,
+    tick_secs: Option<u64>,
+    shutdown_timeout_secs: Option<u64>,
+    page_size: Option<usize>,
+    api_page_size: Option<u32>,
+    directory_ttl_secs: Option<u64>,
+    events_cache_ttl_secs: Option<u64>,
+    log_level: Option<String>,
+    log_format: Option<String>,
+    webhook_url: Option<String>,
+    webhook_address: Option<String>,
+    webhook_path: Option<String>,
+    webhook_secret: Option<String>,
+    metrics_address: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(&'static str, String),
+    Invalid(&'static str, String),
+    MissingToken,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => {
+                write!(f, "failed to read {}: {error}", path.display())
+            }
+            ConfigError::Parse(path, error) => {
+                write!(f, "failed to parse {}: {error}", path.display())
+            }
+            ConfigError::Env(name, value) => write!(f, "invalid value {value:?} in ${name}"),
+            ConfigError::Invalid(key, reason) => write!(f, "invalid `{key}`: {reason}"),
+            ConfigError::MissingToken => {
+                write!(f, "bot token is not set: use `token` or $TELOXIDE_TOKEN")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Loads `path`, or `$AFISHA_CONFIG`, or `afisha.toml` if it exists, and applies
+    /// environment overrides. An explicitly given file must exist.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let explicit = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os("AFISHA_CONFIG").map(PathBuf::from));
+        let path = explicit.clone().unwrap_or_else(|| DEFAULT_PATH.into());
+        let mut file = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).map_err(|error| ConfigError::Parse(path, error))?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound && explicit.is_none() => {
+                FileConfig::default()
+            }
+            Err(error) => return Err(ConfigError::Read(path, error)),
+        };
+        file.apply_env()?;
+        file.validate()
+    }
+
+    pub fn token(&self) -> Result<&str, ConfigError> {
+        self.token.as_deref().ok_or(ConfigError::MissingToken)
+    }
+
+    /// Full public webhook URL, if webhook mode is configured.
+    pub fn webhook(&self) -> Option<Url> {
+        let mut url = self.webhook_url.clone()?;
+        let path = format!("{}{}", url.path().trim_end_matches('/'), self.webhook_path);
+        url.set_path(&path);
+        Some(url)
+    }
+}
+
+impl FileConfig {
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        fn var(name: &str) -> Option<String> {
+            env::var(name).ok().filter(|value| !value.trim().is_empty())
+        }
+        fn parse<T: FromStr>(name: &'static str) -> Result<Option<T>, ConfigError> {
+            var(name)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| ConfigError::Env(name, value))
+                })
+                .transpose()
+        }
+
+        if let Some(token) = var("TELOXIDE_TOKEN") {
+            self.token = Some(token);
+        }
+        if let Some(db_url) = var("AFISHA_DB_URL") {
+            self.db_url = Some(db_url);
+        }
+        if let Some(api_root) = var("AFISHA_API_ROOT") {
+            self.api_root = Some(api_root);
+        }
+        if let Some(ids) = var("ADMIN_IDS") {
+            let ids = ids
+                .split(',')
+                .map(|id| id.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| ConfigError::Env("ADMIN_IDS", ids.clone()))?;
+            self.admin_ids = Some(ids);
+        }
+        if let Some(timezone) = var("AFISHA_TIMEZONE") {
+            self.timezone = Some(timezone);
+        }
+        if let Some(concurrency) = parse("AFISHA_CONCURRENCY")? {
+            self.concurrency = Some(concurrency);
+        }
+        if let Some(tick) = parse("AFISHA_TICK_SECS")? {
+            self.tick_secs = Some(tick);
+        }
+        if let Some(timeout) = parse("AFISHA_SHUTDOWN_TIMEOUT_SECS")? {
+            self.shutdown_timeout_secs = Some(timeout);
+        }
+        if let Some(ttl) = parse("AFISHA_DIRECTORY_TTL_SECS")? {
+            self.directory_ttl_secs = Some(ttl);
+        }
+        if let Some(ttl) = parse("AFISHA_EVENTS_CACHE_TTL_SECS")? {
+            self.events_cache_ttl_secs = Some(ttl);
+        }
+        if let Some(level) = var("AFISHA_LOG").or_else(|| var("RUST_LOG")) {
+            self.log_level = Some(level);
+        }
+        if let Some(format) = var("AFISHA_LOG_FORMAT") {
+            self.log_format = Some(format);
+        }
+        if let Some(url) = var("AFISHA_WEBHOOK_URL") {
+            self.webhook_url = Some(url);
+        }
+        if let Some(address) = var("AFISHA_WEBHOOK_ADDRESS") {
+            self.webhook_address = Some(address);
+        }
+        if let Some(path) = var("AFISHA_WEBHOOK_PATH") {
+            self.webhook_path = Some(path);
+        }
+        if let Some(secret) = var("AFISHA_WEBHOOK_SECRET") {
+            self.webhook_secret = Some(secret);
+        }
+        if let Some(address) = var("AFISHA_METRICS_ADDRESS") {
+            self.metrics_address = Some(address);
+        }
+        Ok(())
+    }
+
+    fn validate(self) -> Result<Config, ConfigError> {
+        let default = Config::default();
+        let positive = |key: &'static str, value: Option<u64>, default: u64| match value {
+            Some(0) => Err(ConfigError::Invalid(
+                key,
+                "must be greater than zero".into(),
+            )),
+            Some(value) => Ok(value),
+            None => Ok(default),
+        };
+
+        let mut api_root = self.api_root.unwrap_or(default.api_root);
+        if !api_root.starts_with("http://") && !api_root.starts_with("https://") {
+            return Err(ConfigError::Invalid(
+                "api_root",
+                format!("{api_root:?} is not an http(s) URL"),
+            ));
+        }
+        if !api_root.ends_with('/') {
+            api_root.push('/');
+        }
+        let timezone = match self.timezone {
+            Some(name) => name.parse().map_err(|_| {
+                ConfigError::Invalid("timezone", format!("unknown timezone {name:?}"))
+            })?,
+            None => default.timezone,
+        };
+        let log_level = self.log_level.unwrap_or(default.log_level);
+        if let Err(error) = EnvFilter::try_new(&log_level) {
+            return Err(ConfigError::Invalid("log_level", error.to_string()));
+        }
+        let log_format = match self.log_format.as_deref() {
+            Some("text") => LogFormat::Text,
+            Some("json") => LogFormat::Json,
+            Some(format) => {
+                return Err(ConfigError::Invalid(
+                    "log_format",
+                    format!("expected \"text\" or \"json\", got {format:?}"),
+                ))
+            }
+            None => default.log_format,
+        };
+        let db_url = self.db_url.unwrap_or(default.db_url);
+        if db_url.trim().is_empty() {
+            return Err(ConfigError::Invalid("db_url", "must not be empty".into()));
+        }
+        let webhook_url = match self.webhook_url.filter(|url| !url.trim().is_empty()) {
+            Some(url) => match Url::parse(&url) {
+                Ok(url) if url.scheme() == "https" => Some(url),
+                Ok(_) => {
+                    return Err(ConfigError::Invalid(
+                        "webhook_url",
+                        "Telegram only delivers updates over https".into(),
+                    ))
+                }
+                Err(error) => return Err(ConfigError::Invalid("webhook_url", error.to_string())),
+            },
+            None => None,
+        };
+        let address = |key: &'static str, value: Option<String>, default: SocketAddr| match value {
+            Some(address) => address
+                .parse()
+                .map_err(|_| ConfigError::Invalid(key, format!("{address:?} is not host:port"))),
+            None => Ok(default),
+        };
+        let webhook_address = address(
+            "webhook_address",
+            self.webhook_address,
+            default.webhook_address,
+        )?;
+        let metrics_address = address(
+            "metrics_address",
+            self.metrics_address,
+            default.metrics_address,
+        )?;
+        let webhook_path = self.webhook_path.unwrap_or(default.webhook_path);
+        if !webhook_path.starts_with('/') {
+            return Err(ConfigError::Invalid(
+                "webhook_path",
+                "must start with `/`".into(),
+            ));
+        }
+        // Telegram accepts 1-256 characters from `A-Za-z0-9_-`.
+        let webhook_secret = self.webhook_secret.filter(|secret| !secret.is_empty());
+        if let Some(secret) = &webhook_secret {
+            let valid = secret.len() <= 256
+                && secret
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid {
+                return Err(ConfigError::Invalid(
+                    "webhook_secret",
+                    "use 1-256 characters from A-Z, a-z, 0-9, `_` and `-`".into(),
+                ));
+            }
+        }
+
+        Ok(Config {
+            token: self.token.filter(|token| !token.trim().is_empty()),
+            db_url,
+            api_root,
+            admin_ids: self.admin_ids.unwrap_or_default(),
+            timezone,
+            concurrency: positive(
+                "concurrency",
+                self.concurrency.map(|value| value as u64),
+                default.concurrency as u64,
+            )? as usize,
+            tick: Duration::from_secs(positive(
+                "tick_secs",
+                self.tick_secs,
+                default.tick.as_secs(),
+            )?),
+            shutdown_timeout: self
+                .shutdown_timeout_secs
+                .map_or(default.shutdown_timeout, Duration::from_secs),
+            page_size: positive(
+                "page_size",
+                self.page_size.map(|value| value as u64),
+                default.page_size as u64,
+            )? as usize,
+            api_page_size: positive(
+                "api_page_size",
+                self.api_page_size.map(u64::from),
+                default.api_page_size.into(),
+            )? as u32,
+            directory_ttl: Duration::from_secs(positive(
+                "directory_ttl_secs",
+                self.directory_ttl_secs,
+                default.directory_ttl.as_secs(),
+            )?),
+            events_cache_ttl: self
+                .events_cache_ttl_secs
+                .map_or(default.events_cache_ttl, Duration::from_secs),
+            log_level,
+            log_format,
+            webhook_url,
+            webhook_address,
+            webhook_path,
+            webhook_secret,
+            metrics_address,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(toml: &str) -> Result<Config, ConfigError> {
+        toml::from_str::<FileConfig>(toml).unwrap().validate()
+    }
+
+    fn rejected_key(toml: &str) -> &'static str {
+        match validate(toml) {
+            Err(ConfigError::Invalid(key, _)) => key,
+            other => panic!("{toml:?} was not rejected: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_zero_sizes() {
+        for key in ["tick_secs", "page_size", "api_page_size", "concurrency"] {
+            assert_eq!(rejected_key(&format!("{key} = 0")), key);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let cases = [
+            (r#"timezone = "Europe/Atlantis""#, "timezone"),
+            (r#"webhook_url = "http://bot.example.com""#, "webhook_url"),
+            (r#"webhook_url = "bot.example.com""#, "webhook_url"),
+            (r#"webhook_secret = "not secret!""#, "webhook_secret"),
+            (r#"api_root = "ftp://api.example.com/""#, "api_root"),
+            (r#"log_format = "yaml""#, "log_format"),
+            (r#"webhook_path = "telegram""#, "webhook_path"),
+            (r#"metrics_address = "localhost""#, "metrics_address"),
+        ];
+        for (toml, key) in cases {
+            assert_eq!(rejected_key(toml), key, "{toml}");
+        }
+        let long_secret = format!("webhook_secret = \"{}\"", "a".repeat(257));
+        assert_eq!(rejected_key(&long_secret), "webhook_secret");
+    }
+
+    #[test]
+    fn accepts_valid_values() {
+        let config = validate(
+            r#"
+            api_root = "https://api.example.com/v1"
+            timezone = "Asia/Novosibirsk"
+            webhook_url = "https://bot.example.com/hooks"
+            webhook_secret = "s3cret_token-1"
+            tick_secs = 30
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.api_root, "https://api.example.com/v1/");
+        assert_eq!(config.timezone, chrono_tz::Asia::Novosibirsk);
+        assert_eq!(config.tick, Duration::from_secs(30));
+        assert_eq!(
+            config.webhook().unwrap().as_str(),
+            format!("https://bot.example.com/hooks{}", config.webhook_path)
+        );
+
+        let config = validate(r#"api_root = "https://api.example.com/v1/""#).unwrap();
+        assert_eq!(config.api_root, "https://api.example.com/v1/");
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let path = env::temp_dir().join(format!("afisha-config-{}.toml", std::process::id()));
+        fs::write(&path, "tick_secs = 60\ntimezone = \"Asia/Novosibirsk\"\n").unwrap();
+        // No other test reads this variable.
+        env::set_var("AFISHA_TICK_SECS", "15");
+        let config = Config::load(Some(&path));
+        env::remove_var("AFISHA_TICK_SECS");
+        let _ = fs::remove_file(&path);
+
+        let config = config.unwrap();
+        assert_eq!(config.tick, Duration::from_secs(15));
+        assert_eq!(config.timezone, chrono_tz::Asia::Novosibirsk);
+    }
+}
@@ -1,28 +1,61 @@
 use chrono::prelude::*;
-use serde::Serialize;
-use sqlx::{FromRow, Row, SqlitePool};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Row, SqliteConnection, SqlitePool};
 
-pub const DB_URL: &str = "afisha.db";
+use crate::{
+    api::{AgeFilter, EventsInterval},
+    broadcast::BroadcastFilter,
+    category::{Category, Rubric},
+    metrics::metrics,
+};
 
-#[derive(Debug, Clone, FromRow, Serialize)]
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct User {
     pub id: i64,
     pub tg_id: u64,
     pub city: String,
-    pub tags: Vec<String>,
+    #[serde(alias = "tags")]
+    pub categories: Vec<Category>,
     pub notification_time: NaiveTime,
-    pub events_interval: u32,
+    pub events_interval: EventsInterval,
+    pub active: bool,
+    pub paused: bool,
+    pub blocked_at: Option<DateTime<Utc>>,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub last_digest_at: Option<DateTime<Utc>>,
+    pub language: Option<String>,
+    /// When the scheduler next has to look at this user; `None` until it works that
+    /// out after a settings change.
+    #[serde(default)]
+    pub next_fire_at: Option<DateTime<Utc>>,
+    /// Most a ticket may cost in rubles; `None` means any price.
+    #[serde(default)]
+    pub max_price: Option<u32>,
+    #[serde(default)]
+    pub free_only: bool,
+    #[serde(default)]
+    pub age_filter: AgeFilter,
 }
 
-impl User {
-    pub fn default() -> Self {
+impl Default for User {
+    fn default() -> Self {
         Self {
             id: -1,
             tg_id: 1,
             city: "w".into(),
-            tags: Vec::new(),
+            categories: Vec::new(),
             notification_time: Local::now().time(),
-            events_interval: 1,
+            events_interval: EventsInterval::Today,
+            active: true,
+            paused: false,
+            blocked_at: None,
+            paused_until: None,
+            last_digest_at: None,
+            language: None,
+            next_fire_at: None,
+            max_price: None,
+            free_only: false,
+            age_filter: AgeFilter::Any,
         }
     }
 }
@@ -32,15 +65,62 @@ pub struct UserFilter {
     pub id: Option<i64>,
     pub tg_id: Option<u64>,
     pub city: Option<String>,
-    pub tags: Option<Vec<String>>,
+    pub categories: Option<Vec<Category>>,
     pub notification_time: Option<NaiveTime>,
-    pub events_interval: Option<u32>,
+    pub events_interval: Option<EventsInterval>,
+}
+
+// Categories live in `user_categories`; every user query folds them back into a JSON
+// column so they all share `user_from_row`.
+const SELECT_USERS: &str = "
+    SELECT users.*, (
+        SELECT json_group_array(category) FROM (
+            SELECT category FROM user_categories WHERE user_id = users.id ORDER BY rowid
+        )
+    ) AS categories
+    FROM users
+";
+
+fn user_from_row(row: &SqliteRow) -> User {
+    User {
+        id: row.get(0),
+        tg_id: serde_json::from_str(row.get(1)).unwrap(),
+        city: row.get(2),
+        categories: serde_json::from_str(row.get("categories")).unwrap(),
+        notification_time: row.get(4),
+        events_interval: events_interval_from_row(row, 5),
+        active: row.get("active"),
+        paused: row.get("paused"),
+        blocked_at: row.get("blocked_at"),
+        paused_until: row.get("paused_until"),
+        last_digest_at: row.get("last_digest_at"),
+        language: row.get("language"),
+        next_fire_at: row.get("next_fire_at"),
+        max_price: row.get("max_price"),
+        free_only: row.get("free_only"),
+        age_filter: row
+            .get::<Option<String>, _>("age_filter")
+            .map(|text| serde_json::from_str(&text).unwrap())
+            .unwrap_or_default(),
+    }
+}
+
+// Rows written before intervals became an enum hold a plain number of days.
+fn events_interval_from_row(row: &SqliteRow, index: usize) -> EventsInterval {
+    if let Ok(text) = row.try_get::<String, _>(index) {
+        return serde_json::from_str(&text).unwrap();
+    }
+    match row.get::<i64, _>(index) {
+        ..=1 => EventsInterval::Today,
+        2..=7 => EventsInterval::Week,
+        _ => EventsInterval::Month,
+    }
 }
 
 pub async fn init_db(pool: &SqlitePool) {
     let mut tx = pool.begin().await.unwrap();
 
-    let result = sqlx::query(
+    sqlx::query(
         "
         CREATE TABLE IF NOT EXISTS users (
             id integer primary key,
@@ -48,7 +128,7 @@ pub async fn init_db(pool: &SqlitePool) {
             city text,
             tags text,
             notification_time text,
-            events_interval integer
+            events_interval text
         )
         ",
     )
@@ -56,23 +136,202 @@ pub async fn init_db(pool: &SqlitePool) {
     .await
     .unwrap();
 
+    add_column(&mut tx, "users", "active", "integer NOT NULL DEFAULT 1").await;
+    add_column(&mut tx, "users", "paused", "integer NOT NULL DEFAULT 0").await;
+    add_column(&mut tx, "users", "blocked_at", "text").await;
+    add_column(&mut tx, "users", "paused_until", "text").await;
+    add_column(&mut tx, "users", "last_digest_at", "text").await;
+    add_column(&mut tx, "users", "language", "text").await;
+    add_column(&mut tx, "users", "next_fire_at", "text").await;
+    add_column(&mut tx, "users", "max_price", "integer").await;
+    add_column(&mut tx, "users", "free_only", "integer NOT NULL DEFAULT 0").await;
+    add_column(&mut tx, "users", "age_filter", "text").await;
+    // Only users the scheduler can send to are indexed, so stopped, paused and blocked
+    // chats don't add to every tick. It replaces an earlier index over all users.
+    sqlx::query("DROP INDEX IF EXISTS users_next_fire_at")
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS users_due ON users (next_fire_at) WHERE active AND NOT paused",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS user_categories (
+            user_id integer NOT NULL,
+            category text NOT NULL,
+            PRIMARY KEY (user_id, category)
+        )
+        ",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS user_categories_category ON user_categories (category)",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    migrate_tags(&mut tx).await;
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS rubrics (
+            city text NOT NULL,
+            id text NOT NULL,
+            name text NOT NULL,
+            position integer NOT NULL,
+            fetched_at text NOT NULL,
+            PRIMARY KEY (city, id)
+        )
+        ",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS admins (
+            tg_id integer primary key
+        )
+        ",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS broadcasts (
+            id integer primary key,
+            admin_chat_id integer NOT NULL,
+            status_message_id integer NOT NULL,
+            message text NOT NULL,
+            filter text NOT NULL,
+            cursor integer NOT NULL DEFAULT 0,
+            total integer NOT NULL DEFAULT 0,
+            sent integer NOT NULL DEFAULT 0,
+            failed integer NOT NULL DEFAULT 0,
+            skipped integer NOT NULL DEFAULT 0,
+            status text NOT NULL DEFAULT 'running',
+            created_at text NOT NULL,
+            finished_at text
+        )
+        ",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS outbox (
+            id integer primary key,
+            idempotency_key text NOT NULL UNIQUE,
+            chat_id integer NOT NULL,
+            text text NOT NULL,
+            reply_markup text,
+            status text NOT NULL DEFAULT 'pending',
+            attempts integer NOT NULL DEFAULT 0,
+            next_attempt_at text NOT NULL,
+            last_error text,
+            created_at text NOT NULL,
+            sent_at text
+        )
+        ",
+    )
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    sqlx::query("CREATE INDEX IF NOT EXISTS outbox_pending ON outbox (status, chat_id, id)")
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
     tx.commit().await.unwrap();
 }
 
+// Categories used to be a JSON list in `users.tags`, typed by hand and not always
+// trimmed. Known ones move to `user_categories` and the column is cleared, so this
+// does nothing once a database has been migrated. A user none of whose tags is known
+// keeps them all as `Category::Other` rather than ending up with no categories.
+async fn migrate_tags(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>) {
+    let known = serde_json::to_string(&Category::ALL).unwrap();
+    sqlx::query(
+        "
+        INSERT OR IGNORE INTO user_categories (user_id, category)
+        SELECT users.id, trim(tag.value) FROM users, json_each(users.tags) AS tag
+        WHERE users.tags IS NOT NULL
+            AND trim(tag.value) IN (SELECT value FROM json_each($1))
+        ORDER BY users.id, tag.key
+        ",
+    )
+    .bind(known)
+    .execute(&mut **tx)
+    .await
+    .unwrap();
+    sqlx::query(
+        "
+        INSERT OR IGNORE INTO user_categories (user_id, category)
+        SELECT users.id, trim(tag.value) FROM users, json_each(users.tags) AS tag
+        WHERE users.tags IS NOT NULL
+            AND trim(tag.value) != ''
+            AND users.id NOT IN (SELECT user_id FROM user_categories)
+        ORDER BY users.id, tag.key
+        ",
+    )
+    .execute(&mut **tx)
+    .await
+    .unwrap();
+    sqlx::query("UPDATE users SET tags = NULL WHERE tags IS NOT NULL")
+        .execute(&mut **tx)
+        .await
+        .unwrap();
+}
+
+// CREATE TABLE IF NOT EXISTS leaves existing databases alone, so columns added
+// after the first release have to be migrated in explicitly.
+async fn add_column(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    table: &str,
+    column: &str,
+    definition: &str,
+) {
+    let exists = sqlx::query("SELECT 1 FROM pragma_table_info($1) WHERE name = $2")
+        .bind(table)
+        .bind(column)
+        .fetch_optional(&mut **tx)
+        .await
+        .unwrap()
+        .is_some();
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"))
+            .execute(&mut **tx)
+            .await
+            .unwrap();
+    }
+}
+
 pub async fn insert_user(pool: &SqlitePool, user: User) {
     let mut tx = pool.begin().await.unwrap();
 
     let old_user = get_user(pool, user.tg_id).await;
     match old_user {
         Some(old_user) => {
-            let tg_id = old_user.tg_id.clone();
+            let tg_id = old_user.tg_id;
             update_user(
                 pool,
                 UserFilter {
                     id: Some(user.id),
                     tg_id: Some(user.tg_id),
                     city: Some(user.city),
-                    tags: Some(user.tags),
+                    categories: Some(user.categories),
                     notification_time: Some(user.notification_time),
                     events_interval: Some(user.events_interval),
                 },
@@ -84,30 +343,92 @@ pub async fn insert_user(pool: &SqlitePool, user: User) {
         None => {
             let result = sqlx::query(
                 "
-                INSERT INTO users (tg_id, city, tags, notification_time, events_interval)
-                VALUES ($1, $2, $3, $4, $5)
+                INSERT INTO users (
+                    tg_id, city, notification_time, events_interval, language, max_price,
+                    free_only, age_filter
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
                 ",
             )
             .bind(serde_json::to_string(&user.tg_id).unwrap())
             .bind(user.city)
-            .bind(serde_json::to_string(&user.tags).unwrap())
             .bind(user.notification_time)
-            .bind(user.events_interval)
+            .bind(serde_json::to_string(&user.events_interval).unwrap())
+            .bind(user.language)
+            .bind(user.max_price)
+            .bind(user.free_only)
+            .bind(serde_json::to_string(&user.age_filter).unwrap())
             .execute(&mut *tx)
             .await
             .unwrap();
+            set_categories(&mut tx, result.last_insert_rowid(), &user.categories)
+                .await
+                .unwrap();
         }
     }
 
     tx.commit().await.unwrap();
 }
 
+/// Writes every column of `user`, inserting the row if its `tg_id` is new. Used by
+/// import, which has to restore pause and delivery state as well as settings; `id`
+/// is local to each database and is not copied.
+pub async fn upsert_user(pool: &SqlitePool, user: &User) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let tg_id = serde_json::to_string(&user.tg_id).unwrap();
+    let existing: Option<i64> = sqlx::query_scalar("SELECT id FROM users WHERE tg_id = $1")
+        .bind(&tg_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let query = match existing {
+        Some(_) => {
+            "
+            UPDATE users SET city = $2, notification_time = $3, events_interval = $4,
+                active = $5, paused = $6, blocked_at = $7, paused_until = $8,
+                last_digest_at = $9, language = $10, next_fire_at = $11, max_price = $12,
+                free_only = $13, age_filter = $14
+            WHERE tg_id = $1
+            "
+        }
+        None => {
+            "
+            INSERT INTO users (
+                tg_id, city, notification_time, events_interval, active, paused, blocked_at,
+                paused_until, last_digest_at, language, next_fire_at, max_price, free_only,
+                age_filter
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            "
+        }
+    };
+    let result = sqlx::query(query)
+        .bind(&tg_id)
+        .bind(&user.city)
+        .bind(user.notification_time)
+        .bind(serde_json::to_string(&user.events_interval).unwrap())
+        .bind(user.active)
+        .bind(user.paused)
+        .bind(user.blocked_at)
+        .bind(user.paused_until)
+        .bind(user.last_digest_at)
+        .bind(&user.language)
+        .bind(user.next_fire_at)
+        .bind(user.max_price)
+        .bind(user.free_only)
+        .bind(serde_json::to_string(&user.age_filter).unwrap())
+        .execute(&mut *tx)
+        .await?;
+    let id = existing.unwrap_or_else(|| result.last_insert_rowid());
+    set_categories(&mut tx, id, &user.categories).await?;
+    tx.commit().await
+}
+
 pub async fn get_user(pool: &SqlitePool, id: u64) -> Option<User> {
     let mut tx = pool.begin().await.unwrap();
 
     let rows = sqlx::query(&format!(
         "
-        SELECT * FROM users
+        {SELECT_USERS}
         WHERE tg_id = {id}
         "
     ))
@@ -117,19 +438,10 @@ pub async fn get_user(pool: &SqlitePool, id: u64) -> Option<User> {
 
     let mut users = Vec::new();
 
-    if rows.last().is_none() {
-        return None;
-    }
+    rows.last()?;
 
     for row in rows {
-        users.push(User {
-            id: row.get(0),
-            tg_id: serde_json::from_str(row.get(1)).unwrap(),
-            city: row.get(2),
-            tags: serde_json::from_str(row.get(3)).unwrap(),
-            notification_time: row.get(4),
-            events_interval: row.get(5),
-        });
+        users.push(user_from_row(&row));
     }
 
     tx.commit().await.unwrap();
@@ -137,12 +449,27 @@ pub async fn get_user(pool: &SqlitePool, id: u64) -> Option<User> {
     Some(users[0].clone())
 }
 
+/// Users in primary key order, for jobs that walk the table in batches.
+pub async fn get_users_after(
+    pool: &SqlitePool,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<User>, sqlx::Error> {
+    let rows = sqlx::query(&format!("{SELECT_USERS} WHERE id > $1 ORDER BY id LIMIT $2"))
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(user_from_row).collect())
+}
+
 pub async fn get_all_users(pool: &SqlitePool) -> Option<Vec<User>> {
+    let _timer = metrics().db_queries.start_timer();
     let mut tx = pool.begin().await.unwrap();
 
     let rows = sqlx::query(&format!(
         "
-        SELECT * FROM users
+        {SELECT_USERS}
         "
     ))
     .fetch_all(&mut *tx)
@@ -153,19 +480,10 @@ pub async fn get_all_users(pool: &SqlitePool) -> Option<Vec<User>> {
 
     let mut users = Vec::new();
 
-    if rows.last().is_none() {
-        return None;
-    }
+    rows.last()?;
 
     for row in rows {
-        users.push(User {
-            id: row.get(0),
-            tg_id: serde_json::from_str(row.get(1)).unwrap(),
-            city: row.get(2),
-            tags: serde_json::from_str(row.get(3)).unwrap(),
-            notification_time: row.get(4),
-            events_interval: row.get(5),
-        });
+        users.push(user_from_row(&row));
     }
 
     Some(users)
@@ -176,9 +494,8 @@ pub async fn update_user(
     values: UserFilter,
     tg_id: u64
 ) -> Result<(), sqlx::Error> {
-    let mut conn = pool.acquire().await?;
-
     let old_user = get_user(pool, tg_id).await.unwrap();
+    let mut tx = pool.begin().await?;
 
     let id_insert = old_user.id;
     let tg_id_insert: i64 = match values.tg_id {
@@ -189,9 +506,9 @@ pub async fn update_user(
         Some(city) => city,
         None => old_user.city,
     };
-    let tags_insert = match values.tags {
-        Some(tags) => tags,
-        None => old_user.tags,
+    let categories_insert = match values.categories {
+        Some(categories) => categories,
+        None => old_user.categories,
     };
     let notification_time_insert = match values.notification_time {
         Some(notification_time) => notification_time,
@@ -202,18 +519,860 @@ pub async fn update_user(
         None => old_user.events_interval,
     };
 
-    let result = sqlx::query(
+    sqlx::query(
         "
-        UPDATE users SET tg_id = $1, city = $2, tags = $3, notification_time = $4, events_interval = $5
+        UPDATE users SET tg_id = $1, city = $2, notification_time = $3, events_interval = $4,
+            next_fire_at = NULL
         WHERE tg_id = $1
         "
     )
     .bind(tg_id_insert)
     .bind(city_insert)
-    .bind(serde_json::to_string(&tags_insert).unwrap())
     .bind(notification_time_insert)
-    .bind(events_interval_insert)
-    .execute(&mut *conn)
+    .bind(serde_json::to_string(&events_interval_insert).unwrap())
+    .execute(&mut *tx)
+    .await?;
+    set_categories(&mut tx, id_insert, &categories_insert).await?;
+    tx.commit().await
+}
+
+async fn set_categories(
+    conn: &mut SqliteConnection,
+    user_id: i64,
+    categories: &[Category],
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM user_categories WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    for category in categories {
+        sqlx::query("INSERT OR IGNORE INTO user_categories (user_id, category) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(category.as_str())
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Users subscribed to `category`.
+pub async fn get_users_in_category(
+    pool: &SqlitePool,
+    category: &Category,
+) -> Result<Vec<User>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "
+        {SELECT_USERS}
+        WHERE id IN (SELECT user_id FROM user_categories WHERE category = $1)
+        ORDER BY id
+        "
+    ))
+    .bind(category.as_str())
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(user_from_row).collect())
+}
+
+/// How many users follow each category, most popular first.
+pub async fn count_users_by_category(
+    pool: &SqlitePool,
+) -> Result<Vec<(Category, i64)>, sqlx::Error> {
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        "
+        SELECT category, COUNT(*) AS users FROM user_categories
+        GROUP BY category
+        ORDER BY users DESC, category
+        ",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(category, users)| (Category::from_id(&category), users))
+        .collect())
+}
+
+/// Rubrics cached for `city` and when they were fetched, if any are.
+pub async fn get_rubrics(
+    pool: &SqlitePool,
+    city: &str,
+) -> Result<Option<(Vec<Rubric>, DateTime<Utc>)>, sqlx::Error> {
+    let rows: Vec<(String, String, DateTime<Utc>)> = sqlx::query_as(
+        "SELECT id, name, fetched_at FROM rubrics WHERE city = $1 ORDER BY position",
+    )
+    .bind(city)
+    .fetch_all(pool)
+    .await?;
+    let Some(fetched_at) = rows.iter().map(|(_, _, fetched_at)| *fetched_at).min() else {
+        return Ok(None);
+    };
+    let rubrics = rows
+        .into_iter()
+        .map(|(id, name, _)| Rubric {
+            category: Category::from_id(&id),
+            name,
+        })
+        .collect();
+    Ok(Some((rubrics, fetched_at)))
+}
+
+/// Replaces the rubrics cached for `city`.
+pub async fn save_rubrics(
+    pool: &SqlitePool,
+    city: &str,
+    rubrics: &[Rubric],
+    fetched_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM rubrics WHERE city = $1")
+        .bind(city)
+        .execute(&mut *tx)
+        .await?;
+    for (position, rubric) in rubrics.iter().enumerate() {
+        sqlx::query(
+            "
+            INSERT OR IGNORE INTO rubrics (city, id, name, position, fetched_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ",
+        )
+        .bind(city)
+        .bind(rubric.category.as_str())
+        .bind(&rubric.name)
+        .bind(position as i64)
+        .bind(fetched_at)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn clear_rubrics(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM rubrics").execute(pool).await?;
+    Ok(())
+}
+
+pub async fn set_active(pool: &SqlitePool, tg_id: u64, active: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET active = $1, next_fire_at = NULL WHERE tg_id = $2")
+        .bind(active)
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_paused(pool: &SqlitePool, tg_id: u64, paused: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET paused = $1, next_fire_at = NULL WHERE tg_id = $2")
+        .bind(paused)
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_paused_until(
+    pool: &SqlitePool,
+    tg_id: u64,
+    paused_until: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET paused_until = $1, next_fire_at = NULL WHERE tg_id = $2")
+        .bind(paused_until)
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_max_price(
+    pool: &SqlitePool,
+    tg_id: u64,
+    max_price: Option<u32>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET max_price = $1 WHERE tg_id = $2")
+        .bind(max_price)
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_free_only(
+    pool: &SqlitePool,
+    tg_id: u64,
+    free_only: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET free_only = $1 WHERE tg_id = $2")
+        .bind(free_only)
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_age_filter(
+    pool: &SqlitePool,
+    tg_id: u64,
+    age_filter: AgeFilter,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET age_filter = $1 WHERE tg_id = $2")
+        .bind(serde_json::to_string(&age_filter).unwrap())
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_last_digest_at(
+    pool: &SqlitePool,
+    tg_id: u64,
+    sent_at: DateTime<Utc>,
+    next_fire_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        UPDATE users SET last_digest_at = $1, next_fire_at = $2
+        WHERE tg_id = $3
+        ",
+    )
+    .bind(sent_at)
+    .bind(next_fire_at)
+    .bind(tg_id as i64)
+    .execute(pool)
     .await?;
     Ok(())
 }
+
+/// Users whose digest may be due at `now`. Settings changes reset `next_fire_at` to
+/// NULL instead of computing it, since only the scheduler knows the timezone, so
+/// those users are returned too and get rescheduled on the next tick.
+pub async fn get_due_users(
+    pool: &SqlitePool,
+    now: DateTime<Utc>,
+) -> Result<Vec<User>, sqlx::Error> {
+    let _timer = metrics().db_queries.start_timer();
+    let rows = sqlx::query(&format!(
+        "
+        {SELECT_USERS}
+        WHERE active AND NOT paused AND (next_fire_at IS NULL OR next_fire_at <= $1)
+        "
+    ))
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(user_from_row).collect())
+}
+
+pub async fn set_next_fire_at(
+    pool: &SqlitePool,
+    schedule: &[(u64, Option<DateTime<Utc>>)],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for (tg_id, next_fire_at) in schedule {
+        sqlx::query("UPDATE users SET next_fire_at = $1 WHERE tg_id = $2")
+            .bind(next_fire_at)
+            .bind(*tg_id as i64)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Marks a chat the bot can no longer write to, e.g. after the user blocked it.
+pub async fn deactivate_user(
+    pool: &SqlitePool,
+    tg_id: u64,
+    blocked_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET active = 0, blocked_at = $1, next_fire_at = NULL WHERE tg_id = $2")
+        .bind(blocked_at)
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn reactivate_user(pool: &SqlitePool, tg_id: u64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET active = 1, blocked_at = NULL, next_fire_at = NULL WHERE tg_id = $1")
+        .bind(tg_id as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// What /resume does: lifts any pause and, like /start, forgets that the chat was
+/// blocked.
+pub async fn resume_user(pool: &SqlitePool, tg_id: u64) -> Result<(), sqlx::Error> {
+    set_paused(pool, tg_id, false).await?;
+    set_paused_until(pool, tg_id, None).await?;
+    reactivate_user(pool, tg_id).await
+}
+
+// Every table holding per-chat data must be cleared here, inside the same
+// transaction, so a user is never left half-deleted.
+pub async fn delete_user(pool: &SqlitePool, tg_id: u64) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "DELETE FROM user_categories WHERE user_id IN (SELECT id FROM users WHERE tg_id = $1)",
+    )
+    .bind(tg_id as i64)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM users WHERE tg_id = $1")
+        .bind(tg_id as i64)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM admins WHERE tg_id = $1")
+        .bind(tg_id as i64)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM broadcasts WHERE admin_chat_id = $1")
+        .bind(tg_id as i64)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM outbox WHERE chat_id = $1")
+        .bind(tg_id as i64)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Cheap query used by the readiness check.
+pub async fn ping(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let _timer = metrics().db_queries.start_timer();
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+pub async fn count_active_users(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    let _timer = metrics().db_queries.start_timer();
+    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE active AND NOT paused")
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn vacuum(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query("VACUUM").execute(pool).await?;
+    Ok(())
+}
+
+/// Writes a consistent copy of the database to `path` while it stays online.
+pub async fn backup(pool: &SqlitePool, path: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("VACUUM INTO $1")
+        .bind(path)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_admins(pool: &SqlitePool) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT tg_id FROM admins")
+        .fetch_all(pool)
+        .await
+}
+
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub id: i64,
+    pub admin_chat_id: i64,
+    pub status_message_id: i32,
+    pub message: String,
+    pub filter: BroadcastFilter,
+    pub cursor: i64,
+    pub total: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub skipped: i64,
+}
+
+fn broadcast_from_row(row: &SqliteRow) -> Broadcast {
+    Broadcast {
+        id: row.get("id"),
+        admin_chat_id: row.get("admin_chat_id"),
+        status_message_id: row.get("status_message_id"),
+        message: row.get("message"),
+        filter: serde_json::from_str(row.get("filter")).unwrap(),
+        cursor: row.get("cursor"),
+        total: row.get("total"),
+        sent: row.get("sent"),
+        failed: row.get("failed"),
+        skipped: row.get("skipped"),
+    }
+}
+
+pub async fn insert_broadcast(
+    pool: &SqlitePool,
+    admin_chat_id: i64,
+    status_message_id: i32,
+    message: &str,
+    filter: &BroadcastFilter,
+    total: i64,
+) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query(
+        "
+        INSERT INTO broadcasts (admin_chat_id, status_message_id, message, filter, total, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ",
+    )
+    .bind(admin_chat_id)
+    .bind(status_message_id)
+    .bind(message)
+    .bind(serde_json::to_string(filter).unwrap())
+    .bind(total)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn get_running_broadcasts(pool: &SqlitePool) -> Result<Vec<Broadcast>, sqlx::Error> {
+    let rows = sqlx::query("SELECT * FROM broadcasts WHERE status = 'running' ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(broadcast_from_row).collect())
+}
+
+pub async fn update_broadcast_progress(
+    pool: &SqlitePool,
+    broadcast: &Broadcast,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "
+        UPDATE broadcasts SET cursor = $1, sent = $2, failed = $3, skipped = $4
+        WHERE id = $5
+        ",
+    )
+    .bind(broadcast.cursor)
+    .bind(broadcast.sent)
+    .bind(broadcast.failed)
+    .bind(broadcast.skipped)
+    .bind(broadcast.id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn finish_broadcast(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE broadcasts SET status = 'done', finished_at = $1 WHERE id = $2")
+        .bind(Utc::now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// A message waiting in the outbox. `reply_markup` is the keyboard as JSON.
+#[derive(Debug, Clone)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub idempotency_key: String,
+    pub chat_id: i64,
+    pub text: String,
+    pub reply_markup: Option<String>,
+    pub attempts: i64,
+}
+
+/// Queues `messages` in one transaction. Messages whose idempotency key is already
+/// in the outbox are skipped; returns how many were actually queued.
+pub async fn enqueue_messages(
+    pool: &SqlitePool,
+    messages: &[OutboxMessage],
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = Utc::now();
+    let mut queued = 0;
+    for message in messages {
+        let result = sqlx::query(
+            "
+            INSERT OR IGNORE INTO outbox
+                (idempotency_key, chat_id, text, reply_markup, next_attempt_at, created_at)
+            VALUES ($1, $2, $3, $4, $5, $5)
+            ",
+        )
+        .bind(&message.idempotency_key)
+        .bind(message.chat_id)
+        .bind(&message.text)
+        .bind(&message.reply_markup)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        queued += result.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(queued)
+}
+
+/// Pending messages that are due, oldest first. A chat's message is only returned
+/// once everything queued before it for that chat has been delivered or given up on.
+pub async fn get_due_messages(
+    pool: &SqlitePool,
+    now: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<OutboxMessage>, sqlx::Error> {
+    let rows = sqlx::query(
+        "
+        SELECT id, idempotency_key, chat_id, text, reply_markup, attempts FROM outbox
+        WHERE status = 'pending' AND next_attempt_at <= $1
+            AND NOT EXISTS (
+                SELECT 1 FROM outbox earlier
+                WHERE earlier.status = 'pending'
+                    AND earlier.chat_id = outbox.chat_id
+                    AND earlier.id < outbox.id
+            )
+        ORDER BY id
+        LIMIT $2
+        ",
+    )
+    .bind(now)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|row| OutboxMessage {
+            id: row.get("id"),
+            idempotency_key: row.get("idempotency_key"),
+            chat_id: row.get("chat_id"),
+            text: row.get("text"),
+            reply_markup: row.get("reply_markup"),
+            attempts: row.get("attempts"),
+        })
+        .collect())
+}
+
+pub async fn mark_message_sent(pool: &SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE outbox SET status = 'sent', sent_at = $1 WHERE id = $2")
+        .bind(Utc::now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn retry_message(
+    pool: &SqlitePool,
+    id: i64,
+    attempts: i64,
+    next_attempt_at: DateTime<Utc>,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE outbox SET attempts = $1, next_attempt_at = $2, last_error = $3 WHERE id = $4",
+    )
+    .bind(attempts)
+    .bind(next_attempt_at)
+    .bind(error)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Gives up on every pending message for `chat_id`, e.g. after the bot was blocked.
+pub async fn fail_chat_messages(
+    pool: &SqlitePool,
+    chat_id: i64,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE outbox SET status = 'failed', last_error = $1 WHERE chat_id = $2 AND status = 'pending'",
+    )
+    .bind(error)
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn fail_message(pool: &SqlitePool, id: i64, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE outbox SET status = 'failed', last_error = $1 WHERE id = $2")
+        .bind(error)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Drops finished messages created before `before`.
+pub async fn prune_outbox(pool: &SqlitePool, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM outbox WHERE status != 'pending' AND created_at < $1")
+        .bind(before)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh database file per test. An in-memory pool would give every connection
+    /// its own database, and `insert_user` needs two.
+    async fn test_pool(name: &str) -> SqlitePool {
+        let path = std::env::temp_dir().join(format!(
+            "afisha-bot-{name}-{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let pool = SqlitePool::connect(&format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        init_db(&pool).await;
+        pool
+    }
+
+    fn user(tg_id: u64) -> User {
+        User {
+            tg_id,
+            city: "msk".into(),
+            categories: vec![Category::Concert, Category::Other("kids".into())],
+            notification_time: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            language: Some("ru".into()),
+            ..User::default()
+        }
+    }
+
+    async fn count(pool: &SqlitePool, query: &str, tg_id: u64) -> i64 {
+        sqlx::query_scalar(query)
+            .bind(tg_id as i64)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn delete_user_leaves_no_rows() {
+        let pool = test_pool("delete-user").await;
+        for tg_id in [42, 43] {
+            insert_user(&pool, user(tg_id)).await;
+            sqlx::query("INSERT INTO admins (tg_id) VALUES ($1)")
+                .bind(tg_id as i64)
+                .execute(&pool)
+                .await
+                .unwrap();
+            insert_broadcast(&pool, tg_id as i64, 1, "hi", &BroadcastFilter::default(), 2)
+                .await
+                .unwrap();
+            let message = OutboxMessage {
+                id: 0,
+                idempotency_key: format!("test:{tg_id}"),
+                chat_id: tg_id as i64,
+                text: "hi".into(),
+                reply_markup: None,
+                attempts: 0,
+            };
+            enqueue_messages(&pool, &[message]).await.unwrap();
+        }
+
+        delete_user(&pool, 42).await.unwrap();
+
+        let tables = [
+            "SELECT count(*) FROM users WHERE tg_id = $1",
+            "SELECT count(*) FROM admins WHERE tg_id = $1",
+            "SELECT count(*) FROM broadcasts WHERE admin_chat_id = $1",
+            "SELECT count(*) FROM outbox WHERE chat_id = $1",
+        ];
+        for query in tables {
+            assert_eq!(count(&pool, query, 42).await, 0, "{query}");
+            assert_eq!(count(&pool, query, 43).await, 1, "{query}");
+        }
+        let orphans: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM user_categories WHERE user_id NOT IN (SELECT id FROM users)",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(orphans, 0);
+        let other = get_user(&pool, 43).await.unwrap();
+        assert_eq!(other.categories, user(43).categories);
+    }
+
+    #[tokio::test]
+    async fn migrates_unknown_tags() {
+        let pool = test_pool("migrate-tags").await;
+        let legacy = [(42, r#"["foo"," bar"]"#), (43, r#"["foo"," concert "]"#)];
+        for (tg_id, tags) in legacy {
+            insert_user(&pool, user(tg_id)).await;
+            sqlx::query("UPDATE users SET tags = $1 WHERE tg_id = $2")
+                .bind(tags)
+                .bind(tg_id as i64)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        sqlx::query("DELETE FROM user_categories").execute(&pool).await.unwrap();
+
+        init_db(&pool).await;
+
+        let unknown = get_user(&pool, 42).await.unwrap();
+        assert_eq!(
+            unknown.categories,
+            [Category::Other("foo".into()), Category::Other("bar".into())]
+        );
+        let mixed = get_user(&pool, 43).await.unwrap();
+        assert_eq!(mixed.categories, [Category::Concert]);
+        let tags: i64 = sqlx::query_scalar("SELECT count(*) FROM users WHERE tags IS NOT NULL")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tags, 0);
+    }
+
+    fn message(chat_id: i64, key: &str) -> OutboxMessage {
+        OutboxMessage {
+            id: 0,
+            idempotency_key: key.into(),
+            chat_id,
+            text: key.into(),
+            reply_markup: None,
+            attempts: 0,
+        }
+    }
+
+    async fn due_keys(pool: &SqlitePool) -> Vec<String> {
+        get_due_messages(pool, Utc::now(), 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.idempotency_key)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn digest_is_queued_once() {
+        let pool = test_pool("queue-once").await;
+        let date = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let keyboard = teloxide::types::InlineKeyboardMarkup::default();
+        let messages = vec!["first".to_string(), "second".to_string()];
+
+        let queued = crate::outbox::enqueue_digest(&pool, 42, date, messages.clone(), &keyboard)
+            .await
+            .unwrap();
+        assert_eq!(queued, 2);
+        let queued = crate::outbox::enqueue_digest(&pool, 42, date, messages, &keyboard)
+            .await
+            .unwrap();
+        assert_eq!(queued, 0);
+        assert_eq!(count(&pool, "SELECT count(*) FROM outbox WHERE chat_id = $1", 42).await, 2);
+    }
+
+    #[tokio::test]
+    async fn chat_messages_go_out_in_order() {
+        let pool = test_pool("outbox-order").await;
+        let messages = [
+            message(42, "42:0"),
+            message(43, "43:0"),
+            message(42, "42:1"),
+            message(42, "42:2"),
+        ];
+        enqueue_messages(&pool, &messages).await.unwrap();
+        assert_eq!(due_keys(&pool).await, ["42:0", "43:0"]);
+
+        // 42:0 fails and backs off; the rest of that chat waits for it.
+        let first = get_due_messages(&pool, Utc::now(), 1).await.unwrap();
+        let later = Utc::now() + chrono::Duration::hours(1);
+        retry_message(&pool, first[0].id, 1, later, "timeout").await.unwrap();
+        assert_eq!(due_keys(&pool).await, ["43:0"]);
+
+        let pending = get_due_messages(&pool, later, 10).await.unwrap();
+        assert_eq!(pending[0].idempotency_key, "42:0");
+        assert_eq!(pending[0].attempts, 1);
+        mark_message_sent(&pool, pending[0].id).await.unwrap();
+        assert_eq!(due_keys(&pool).await, ["43:0", "42:1"]);
+    }
+
+    #[tokio::test]
+    async fn due_users_skip_stopped_chats() {
+        let pool = test_pool("due-users").await;
+        let past = Utc::now() - chrono::Duration::hours(1);
+        for tg_id in [42, 43, 44, 45] {
+            insert_user(&pool, user(tg_id)).await;
+            set_next_fire_at(&pool, &[(tg_id, Some(past))]).await.unwrap();
+        }
+        deactivate_user(&pool, 43, past).await.unwrap();
+        set_active(&pool, 44, false).await.unwrap();
+        set_paused(&pool, 45, true).await.unwrap();
+
+        let due: Vec<u64> = get_due_users(&pool, Utc::now())
+            .await
+            .unwrap()
+            .iter()
+            .map(|user| user.tg_id)
+            .collect();
+        assert_eq!(due, [42]);
+        assert_eq!(get_user(&pool, 43).await.unwrap().next_fire_at, None);
+
+        let plan: Vec<String> = sqlx::query(
+            "
+            EXPLAIN QUERY PLAN SELECT id FROM users
+            WHERE active AND NOT paused AND (next_fire_at IS NULL OR next_fire_at <= $1)
+            ",
+        )
+        .bind(Utc::now())
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.get("detail"))
+        .collect();
+        assert!(plan.iter().all(|step| step.contains("users_due")), "{plan:?}");
+    }
+
+    #[tokio::test]
+    async fn resume_unblocks_user() {
+        let pool = test_pool("resume-user").await;
+        insert_user(&pool, user(42)).await;
+        set_paused(&pool, 42, true).await.unwrap();
+        set_paused_until(&pool, 42, Some(Utc::now() + chrono::Duration::days(3)))
+            .await
+            .unwrap();
+        deactivate_user(&pool, 42, Utc::now()).await.unwrap();
+
+        resume_user(&pool, 42).await.unwrap();
+
+        let user = get_user(&pool, 42).await.unwrap();
+        assert!(user.active);
+        assert!(!user.paused);
+        assert_eq!(user.blocked_at, None);
+        assert_eq!(user.paused_until, None);
+    }
+
+    #[tokio::test]
+    async fn export_import_round_trip() {
+        let source = test_pool("export").await;
+        insert_user(&source, user(42)).await;
+        insert_user(&source, user(43)).await;
+        set_paused(&source, 42, true).await.unwrap();
+        set_paused_until(&source, 42, Some(Utc::now())).await.unwrap();
+        set_last_digest_at(&source, 42, Utc::now(), Some(Utc::now()))
+            .await
+            .unwrap();
+        set_max_price(&source, 42, Some(1500)).await.unwrap();
+        set_free_only(&source, 42, true).await.unwrap();
+        set_age_filter(&source, 42, AgeFilter::FAMILY).await.unwrap();
+        deactivate_user(&source, 43, Utc::now()).await.unwrap();
+        let export = serde_json::to_string(&get_all_users(&source).await.unwrap()).unwrap();
+
+        // Importing twice checks the update path as well as the insert.
+        let target = test_pool("import").await;
+        let users: Vec<User> = serde_json::from_str(&export).unwrap();
+        for _ in 0..2 {
+            for user in &users {
+                upsert_user(&target, user).await.unwrap();
+            }
+        }
+
+        let without_id = |users: Vec<User>| {
+            let mut users = serde_json::to_value(users).unwrap();
+            for user in users.as_array_mut().unwrap() {
+                user.as_object_mut().unwrap().remove("id");
+            }
+            users
+        };
+        assert_eq!(
+            without_id(get_all_users(&target).await.unwrap()),
+            without_id(users)
+        );
+    }
+}
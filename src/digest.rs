@@ -0,0 +1,157 @@
+use chrono::NaiveDate;
+
+use crate::{
+    api::{get_events, Event},
+    config::Config,
+    db::User,
+};
+
+/// Fetches and renders the digest `user` would receive on `date`, one string per message.
+pub async fn build(
+    config: &Config,
+    user: &User,
+    date: NaiveDate,
+) -> Result<Vec<String>, reqwest::Error> {
+    let events = get_events(
+        config,
+        user.city.clone(),
+        user.categories.clone(),
+        &user.events_interval,
+        date,
+    )
+    .await?;
+    let events = filter(user, events);
+    let mut messages = render(&events, config.page_size);
+    // Filled in when called from the `digest` span of a scheduled delivery.
+    tracing::Span::current().record("events", events.len());
+    // A pause that is still set when the digest goes out has just ended.
+    if user.paused_until.is_some() {
+        match messages.first_mut() {
+            Some(first) => *first = format!("С возвращением! Вот что нашлось:\n{first}"),
+            None => messages.push("С возвращением! Пока событий по вашим настройкам нет.".into()),
+        }
+    }
+    tracing::Span::current().record("messages", messages.len());
+    Ok(messages)
+}
+
+/// Drops events outside `user`'s price and age limits. Events without listed prices
+/// are kept under `max_price`, since they may well be cheap, but not when `free_only`
+/// is set.
+pub fn filter(user: &User, events: Vec<Event>) -> Vec<Event> {
+    events
+        .into_iter()
+        .filter(|event| user.age_filter.allows(event))
+        .filter(|event| !user.free_only || event.is_free())
+        .filter(|event| match (user.max_price, event.min_price()) {
+            (Some(max_price), Some(price)) => price <= max_price,
+            _ => true,
+        })
+        .collect()
+}
+
+pub fn render(events: &[Event], page_size: usize) -> Vec<String> {
+    events
+        .chunks(page_size)
+        .map(|chunk| {
+            let mut output = String::new();
+            for event in chunk {
+                let price = match event.min_price() {
+                    Some(0) => " — бесплатно".to_string(),
+                    Some(price) => format!(" — от {price} ₽"),
+                    None => String::new(),
+                };
+                output = format!(
+                    "{output}\n{}{price}\nhttps://afisha.yandex.ru/{}/n",
+                    event.title, event.url
+                );
+            }
+            output
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::events_from_json;
+
+    /// A trimmed `events/actual` page; prices are in kopecks.
+    const FIXTURE: &str = r#"{"data": [
+        {"event": {"id": "cheap", "url": "cheap", "title": "Cheap", "tickets": [
+            {"price": {"min": 50000, "max": 90000}},
+            {"price": {"min": 30000}}
+        ]}},
+        {"event": {"id": "pricey", "url": "pricey", "title": "Pricey", "tickets": [
+            {"price": {"min": 250000, "max": 400000}}
+        ]}},
+        {"event": {"id": "free", "url": "free", "title": "Free", "tickets": [
+            {"price": {"min": 0, "max": 0}}
+        ]}},
+        {"event": {"id": "unlisted", "url": "unlisted", "title": "Unlisted", "tickets": [
+            {"price": null}
+        ]}},
+        {"event": {"id": "no-tickets", "url": "no-tickets", "title": "No tickets"}}
+    ]}"#;
+
+    fn kept(user: &User) -> Vec<String> {
+        let events = events_from_json(FIXTURE).unwrap();
+        filter(user, events)
+            .into_iter()
+            .map(|event| event.id)
+            .collect()
+    }
+
+    #[test]
+    fn converts_prices_to_rubles() {
+        let prices: Vec<_> = events_from_json(FIXTURE)
+            .unwrap()
+            .iter()
+            .map(|event| (event.id.clone(), event.min_price(), event.is_free()))
+            .collect();
+        assert_eq!(
+            prices,
+            [
+                ("cheap".to_string(), Some(300), false),
+                ("pricey".to_string(), Some(2500), false),
+                ("free".to_string(), Some(0), true),
+                ("unlisted".to_string(), None, false),
+                ("no-tickets".to_string(), None, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_price() {
+        let user = User::default();
+        assert_eq!(
+            kept(&user),
+            ["cheap", "pricey", "free", "unlisted", "no-tickets"]
+        );
+
+        let user = User {
+            max_price: Some(300),
+            ..User::default()
+        };
+        assert_eq!(kept(&user), ["cheap", "free", "unlisted", "no-tickets"]);
+
+        let user = User {
+            max_price: Some(299),
+            ..User::default()
+        };
+        assert_eq!(kept(&user), ["free", "unlisted", "no-tickets"]);
+
+        let user = User {
+            free_only: true,
+            ..User::default()
+        };
+        assert_eq!(kept(&user), ["free"]);
+
+        let user = User {
+            max_price: Some(1000),
+            free_only: true,
+            ..User::default()
+        };
+        assert_eq!(kept(&user), ["free"]);
+    }
+}
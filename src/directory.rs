@@ -0,0 +1,101 @@
+use std::sync::{Arc, RwLock};
+
+use chrono::Utc;
+use sqlx::SqlitePool;
+
+use crate::{
+    api::{get_cities, get_rubrics, City},
+    category::{builtin_rubrics, Rubric},
+    config::Config,
+    db,
+};
+
+pub type SharedDirectory = Arc<RwLock<Directory>>;
+
+/// Reference data used to validate user input: Afisha cities. Categories differ
+/// between cities and are looked up with [`categories`].
+#[derive(Debug, Clone)]
+pub struct Directory {
+    pub cities: Vec<City>,
+}
+
+impl Directory {
+    pub async fn load(config: &Config) -> Self {
+        let cities = match get_cities(config).await {
+            Ok(cities) if !cities.is_empty() => cities,
+            Ok(_) => builtin_cities(),
+            Err(error) => {
+                log::warn!("Failed to load cities from Afisha, using built-in list: {error}");
+                builtin_cities()
+            }
+        };
+        Self { cities }
+    }
+
+    /// Afisha id for a city typed either by name ("Москва") or by id ("moscow").
+    pub fn city_id(&self, text: &str) -> Option<String> {
+        let text = text.trim().to_lowercase();
+        self.cities
+            .iter()
+            .find(|city| city.id == text || city.name.to_lowercase() == text)
+            .map(|city| city.id.clone())
+    }
+}
+
+/// Categories offered in `city`: Afisha's rubrics, cached in the database for
+/// `config.directory_ttl`. When Afisha is down a stale cache is used, then the
+/// built-in list.
+pub async fn categories(pool: &SqlitePool, config: &Config, city: &str) -> Vec<Rubric> {
+    let cached = db::get_rubrics(pool, city).await.unwrap_or_else(|error| {
+        log::error!("Failed to load cached categories for {city}: {error}");
+        None
+    });
+    if let Some((rubrics, fetched_at)) = &cached {
+        if (Utc::now() - *fetched_at).to_std().unwrap_or_default() < config.directory_ttl {
+            return rubrics.clone();
+        }
+    }
+    let fallback = || cached.map_or_else(builtin_rubrics, |(rubrics, _)| rubrics);
+    match get_rubrics(config, city).await {
+        Ok(rubrics) if !rubrics.is_empty() => {
+            if let Err(error) = db::save_rubrics(pool, city, &rubrics, Utc::now()).await {
+                log::error!("Failed to cache categories for {city}: {error}");
+            }
+            rubrics
+        }
+        Ok(_) => fallback(),
+        Err(error) => {
+            log::warn!("Failed to load categories for {city} from Afisha: {error}");
+            fallback()
+        }
+    }
+}
+
+/// Categories of `city` as last cached, without asking Afisha; used to show their
+/// names. Empty when nothing is cached.
+pub async fn cached_categories(pool: &SqlitePool, city: &str) -> Vec<Rubric> {
+    match db::get_rubrics(pool, city).await {
+        Ok(cached) => cached.map(|(rubrics, _)| rubrics).unwrap_or_default(),
+        Err(error) => {
+            log::error!("Failed to load cached categories for {city}: {error}");
+            Vec::new()
+        }
+    }
+}
+
+fn builtin_cities() -> Vec<City> {
+    [
+        ("moscow", "Москва"),
+        ("saint-petersburg", "Санкт-Петербург"),
+        ("novosibirsk", "Новосибирск"),
+        ("yekaterinburg", "Екатеринбург"),
+        ("kazan", "Казань"),
+        ("nizhny-novgorod", "Нижний Новгород"),
+    ]
+    .into_iter()
+    .map(|(id, name)| City {
+        id: id.into(),
+        name: name.into(),
+    })
+    .collect()
+}
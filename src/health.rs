@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+use sqlx::SqlitePool;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::Config,
+    db::{count_active_users, ping},
+    metrics::metrics,
+};
+
+#[derive(Clone)]
+struct AppState {
+    pool: SqlitePool,
+    config: Arc<Config>,
+}
+
+/// Serves `/healthz`, `/readyz` and `/metrics` on `metrics_address`.
+pub async fn serve(pool: SqlitePool, config: Arc<Config>, shutdown: CancellationToken) {
+    let address = config.metrics_address;
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(prometheus))
+        .with_state(AppState { pool, config });
+    let server = match axum::Server::try_bind(&address) {
+        Ok(server) => server,
+        Err(error) => {
+            log::error!("Failed to bind health server to {address}: {error}");
+            return;
+        }
+    };
+    log::info!("Serving health checks and metrics on {address}");
+    let server = server
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.cancelled_owned());
+    if let Err(error) = server.await {
+        log::error!("Health server failed: {error}");
+    }
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Ready when the database answers, the scheduler ticked within three periods and
+/// Afisha answered since it was last unreachable.
+async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let mut ready = true;
+    let mut report = Vec::new();
+
+    match ping(&state.pool).await {
+        Ok(()) => report.push("db: ok".to_string()),
+        Err(error) => {
+            ready = false;
+            report.push(format!("db: {error}"));
+        }
+    }
+
+    let stale_after = chrono::Duration::from_std(state.config.tick * 3).unwrap();
+    match metrics().last_tick() {
+        Some(tick) if Utc::now() - tick <= stale_after => {
+            report.push(format!("scheduler: ok, last tick {tick}"))
+        }
+        Some(tick) => {
+            ready = false;
+            report.push(format!("scheduler: stale, last tick {tick}"));
+        }
+        None => {
+            ready = false;
+            report.push("scheduler: not started".into());
+        }
+    }
+
+    let last_success = metrics()
+        .last_afisha_success()
+        .map_or("never".to_string(), |time| time.to_string());
+    if metrics().afisha_ok() {
+        report.push(format!("afisha: ok, last success {last_success}"));
+    } else {
+        ready = false;
+        report.push(format!("afisha: failing, last success {last_success}"));
+    }
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, report.join("\n"))
+}
+
+async fn prometheus(State(state): State<AppState>) -> impl IntoResponse {
+    match count_active_users(&state.pool).await {
+        Ok(count) => metrics().active_users.set(count),
+        Err(error) => log::warn!("Failed to count active users: {error}"),
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().render(),
+    )
+}
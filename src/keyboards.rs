@@ -0,0 +1,188 @@
+use chrono::NaiveTime;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::{
+    api::AgeFilter,
+    category::{self, Rubric},
+    db::User,
+};
+
+pub const TIME_PREFIX: &str = "time:";
+
+#[derive(Debug, PartialEq)]
+pub enum TimeButton {
+    Hour(u32),
+    Time(NaiveTime),
+    Hours,
+    Manual,
+}
+
+/// First step of the time picker: one button per hour.
+pub fn hour_keyboard() -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = (0..24)
+        .collect::<Vec<u32>>()
+        .chunks(6)
+        .map(|hours| {
+            hours
+                .iter()
+                .map(|hour| {
+                    InlineKeyboardButton::callback(
+                        format!("{hour:02}"),
+                        format!("{TIME_PREFIX}hour:{hour}"),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    rows.push(vec![InlineKeyboardButton::callback(
+        "Ввести вручную",
+        format!("{TIME_PREFIX}manual"),
+    )]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Second step of the time picker: quarter-hours within the chosen hour.
+pub fn minute_keyboard(hour: u32) -> InlineKeyboardMarkup {
+    let minutes = [0, 15, 30, 45].map(|minute| {
+        InlineKeyboardButton::callback(
+            format!("{hour:02}:{minute:02}"),
+            format!("{TIME_PREFIX}at:{hour}:{minute}"),
+        )
+    });
+    InlineKeyboardMarkup::new([
+        minutes.to_vec(),
+        vec![
+            InlineKeyboardButton::callback("← Час", format!("{TIME_PREFIX}hours")),
+            InlineKeyboardButton::callback("Ввести вручную", format!("{TIME_PREFIX}manual")),
+        ],
+    ])
+}
+
+pub fn parse_time_button(data: &str) -> Option<TimeButton> {
+    let data = data.strip_prefix(TIME_PREFIX)?;
+    if data == "manual" {
+        return Some(TimeButton::Manual);
+    }
+    if data == "hours" {
+        return Some(TimeButton::Hours);
+    }
+    if let Some(hour) = data.strip_prefix("hour:") {
+        return hour
+            .parse()
+            .ok()
+            .filter(|hour| *hour < 24)
+            .map(TimeButton::Hour);
+    }
+    let (hour, minute) = data.strip_prefix("at:")?.split_once(':')?;
+    NaiveTime::from_hms_opt(hour.parse().ok()?, minute.parse().ok()?, 0).map(TimeButton::Time)
+}
+
+pub const SETTINGS_PREFIX: &str = "settings:";
+
+#[derive(Debug, PartialEq)]
+pub enum SettingsButton {
+    City,
+    Categories,
+    NotificationTime,
+    EventsInterval,
+    MaxPrice,
+    FreeOnly,
+    AgeFilter,
+}
+
+/// The settings menu; `rubrics` of the user's city name the categories.
+pub fn settings_keyboard(user: &User, rubrics: &[Rubric]) -> InlineKeyboardMarkup {
+    let button = |text: String, data: &str| {
+        vec![InlineKeyboardButton::callback(
+            format!("{text} ✏️"),
+            format!("{SETTINGS_PREFIX}{data}"),
+        )]
+    };
+    InlineKeyboardMarkup::new([
+        button(format!("Город: {}", user.city), "city"),
+        button(
+            format!("Категории: {}", category::names(&user.categories, rubrics)),
+            "categories",
+        ),
+        button(
+            format!("Время: {}", user.notification_time.format("%H:%M")),
+            "notification_time",
+        ),
+        button(
+            format!("Интервал: {}", user.events_interval),
+            "events_interval",
+        ),
+        button(format!("Цена: {}", price_limit(user)), "max_price"),
+        vec![InlineKeyboardButton::callback(
+            format!(
+                "Только бесплатные: {}",
+                if user.free_only { "да ✅" } else { "нет" }
+            ),
+            format!("{SETTINGS_PREFIX}free_only"),
+        )],
+        button(format!("Возраст: {}", user.age_filter), "age_filter"),
+    ])
+}
+
+/// The user's `max_price` as shown in settings.
+pub fn price_limit(user: &User) -> String {
+    match user.max_price {
+        Some(max_price) => format!("до {max_price} ₽"),
+        None => "любая".into(),
+    }
+}
+
+pub fn parse_settings_button(data: &str) -> Option<SettingsButton> {
+    match data.strip_prefix(SETTINGS_PREFIX)? {
+        "city" => Some(SettingsButton::City),
+        "categories" => Some(SettingsButton::Categories),
+        "notification_time" => Some(SettingsButton::NotificationTime),
+        "events_interval" => Some(SettingsButton::EventsInterval),
+        "max_price" => Some(SettingsButton::MaxPrice),
+        "free_only" => Some(SettingsButton::FreeOnly),
+        "age_filter" => Some(SettingsButton::AgeFilter),
+        _ => None,
+    }
+}
+
+pub const AGE_PREFIX: &str = "age:";
+
+/// Age filter picker shown in place of the settings keyboard.
+pub fn age_keyboard() -> InlineKeyboardMarkup {
+    let ratings = AgeFilter::RATINGS.map(|age| {
+        InlineKeyboardButton::callback(format!("до {age}+"), format!("{AGE_PREFIX}{age}"))
+    });
+    InlineKeyboardMarkup::new([
+        vec![InlineKeyboardButton::callback(
+            "Семейный режим 👨‍👩‍👧",
+            format!("{AGE_PREFIX}family"),
+        )],
+        ratings.to_vec(),
+        vec![
+            InlineKeyboardButton::callback("Без детских", format!("{AGE_PREFIX}no_kids")),
+            InlineKeyboardButton::callback("Любой", format!("{AGE_PREFIX}any")),
+        ],
+    ])
+}
+
+pub fn parse_age_button(data: &str) -> Option<AgeFilter> {
+    match data.strip_prefix(AGE_PREFIX)? {
+        "family" => Some(AgeFilter::FAMILY),
+        "no_kids" => Some(AgeFilter::NoKids),
+        "any" => Some(AgeFilter::Any),
+        age => age
+            .parse()
+            .ok()
+            .filter(|age| AgeFilter::RATINGS.contains(age))
+            .map(AgeFilter::UpTo),
+    }
+}
+
+pub const PREVIEW_PREFIX: &str = "preview:";
+
+pub fn preview_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Предпросмотр дайджеста",
+        format!("{PREVIEW_PREFIX}tomorrow"),
+    )]])
+}
@@ -0,0 +1,30 @@
+use teloxide::{ApiError, RequestError};
+
+pub mod api;
+pub mod broadcast;
+pub mod category;
+pub mod config;
+pub mod db;
+pub mod digest;
+pub mod directory;
+pub mod health;
+pub mod keyboards;
+pub mod metrics;
+pub mod outbox;
+pub mod parse;
+pub mod rate_limit;
+pub mod scheduler;
+
+/// Errors after which retrying is pointless until the user talks to the bot again.
+pub fn is_chat_unreachable(error: &RequestError) -> bool {
+    matches!(
+        error,
+        RequestError::Api(
+            ApiError::BotBlocked
+                | ApiError::ChatNotFound
+                | ApiError::UserDeactivated
+                | ApiError::BotKicked
+                | ApiError::BotKickedFromSupergroup
+        )
+    )
+}
@@ -1,16 +1,35 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::{Duration, Instant, SystemTime},
-};
+use std::sync::{Arc, RwLock};
 
-use crate::{
-    api::get_events,
-    db::{get_all_users, init_db, DB_URL},
+use admin::AdminCommand;
+use afisha_bot::{
+    api::AgeFilter,
+    broadcast::{self, BroadcastFilter},
+    category::{self, Category, Rubric},
+    config::{Config, LogFormat},
+    db::{
+        delete_user, get_due_users, get_user, init_db, insert_user, reactivate_user, resume_user,
+        set_active, set_age_filter, set_free_only, set_last_digest_at, set_max_price,
+        set_next_fire_at, set_paused, set_paused_until, update_user, User, UserFilter,
+    },
+    digest,
+    directory::{self, Directory, SharedDirectory},
+    health,
+    keyboards::{
+        age_keyboard, hour_keyboard, minute_keyboard, parse_age_button, parse_settings_button,
+        parse_time_button, preview_keyboard, price_limit, settings_keyboard, SettingsButton,
+        TimeButton, AGE_PREFIX, PREVIEW_PREFIX, SETTINGS_PREFIX, TIME_PREFIX,
+    },
+    metrics::metrics,
+    outbox,
+    parse::{
+        parse_age_filter, parse_categories, parse_interval, parse_price, parse_snooze, parse_time,
+        AGE_HINT, INTERVAL_HINT, PRICE_HINT, SNOOZE_HINT, TIME_HINT,
+    },
+    rate_limit::{RateLimiter, TELEGRAM_MESSAGES_PER_SECOND},
+    scheduler::{self, Scheduler, SystemClock},
 };
-use api::CATEGORIES;
-use calendar_duration::CalendarDuration;
-use chrono::{DateTime, Local, NaiveTime, Utc};
-use db::{get_user, insert_user, update_user, User, UserFilter};
+use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+use dptree::{di::DependencySupplier, prelude::DependencyMap};
 use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
 use teloxide::{
     dispatching::{
@@ -18,12 +37,22 @@ use teloxide::{
         UpdateHandler,
     },
     prelude::*,
-    utils::{command::BotCommands, html, markdown}, types::{InlineKeyboardMarkup, KeyboardMarkup, KeyboardButton},
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, KeyboardRemove, MessageId, UpdateKind},
+    update_listeners::webhooks,
+    utils::command::BotCommands,
+    ApiError, RequestError,
 };
-use tokio::time;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::Instrument;
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
-mod api;
-mod db;
+// Admin commands are dialogue handlers too, so they live next to `State` and
+// `Command` rather than in the library.
+mod admin;
 
 #[derive(BotCommands, Clone)]
 #[command(
@@ -35,12 +64,28 @@ enum Command {
     Start,
     #[command(description = "Вывод списка всех команд.")]
     Help,
-    #[command(description = "Редактирование параметров.")]
-    Edit {
-        parameter: String,
-    },
+    #[command(description = "Настройки.")]
+    Settings,
     #[command(description = "Посмотреть параметры пользователя.")]
-    Info
+    Info,
+    #[command(description = "Отменить текущее действие.")]
+    Cancel,
+    #[command(description = "Приостановить рассылку.")]
+    Pause,
+    #[command(description = "Возобновить рассылку.")]
+    Resume,
+    #[command(description = "Отключить рассылку, сохранив настройки.")]
+    Stop,
+    #[command(description = "Удалить все ваши данные.")]
+    Delete,
+    #[command(description = "Показать следующий дайджест прямо сейчас.")]
+    Preview { tg_id: String },
+    #[command(
+        description = "Пауза на срок или до даты: /snooze 3d, /snooze пн, /snooze 2026-11-01."
+    )]
+    Snooze { period: String },
+    #[command(description = "Изменить настройку: /edit price 1500, /edit age 6+.")]
+    Edit { args: String },
 }
 
 type MyDialogue = Dialogue<State, InMemStorage<State>>;
@@ -56,28 +101,77 @@ enum State {
     },
     NotificationTime {
         city: String,
-        categories: Vec<String>,
+        categories: Vec<Category>,
     },
     EventsInterval {
         city: String,
-        categories: Vec<String>,
+        categories: Vec<Category>,
         notification_time: NaiveTime,
     },
-    EditCity,
-    EditCategories,
-    EditNotificationTime,
-    EditEventsInterval,
+    EditCity {
+        menu: MessageId,
+    },
+    EditCategories {
+        menu: MessageId,
+    },
+    EditNotificationTime {
+        menu: MessageId,
+    },
+    EditEventsInterval {
+        menu: MessageId,
+    },
+    EditMaxPrice {
+        menu: MessageId,
+    },
+    ConfirmBroadcast {
+        text: String,
+        filter: BroadcastFilter,
+    },
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Start => "start",
+            State::City => "city",
+            State::Categories { .. } => "categories",
+            State::NotificationTime { .. } => "notification_time",
+            State::EventsInterval { .. } => "events_interval",
+            State::EditCity { .. } => "edit_city",
+            State::EditCategories { .. } => "edit_categories",
+            State::EditNotificationTime { .. } => "edit_notification_time",
+            State::EditEventsInterval { .. } => "edit_events_interval",
+            State::EditMaxPrice { .. } => "edit_max_price",
+            State::ConfirmBroadcast { .. } => "confirm_broadcast",
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
+    let config = match Config::load(None) {
+        Ok(config) => Arc::new(config),
+        Err(error) => {
+            eprintln!("Configuration error: {error}");
+            std::process::exit(1);
+        }
+    };
+    init_tracing(&config);
     log::info!("Starting throw dice bot...");
 
-    let bot = Bot::from_env();
+    let bot = match config.token() {
+        Ok(token) => Bot::new(token),
+        Err(error) => {
+            log::error!("Configuration error: {error}");
+            std::process::exit(1);
+        }
+    };
 
-    if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-        match Sqlite::create_database(DB_URL).await {
+    if !Sqlite::database_exists(&config.db_url)
+        .await
+        .unwrap_or(false)
+    {
+        match Sqlite::create_database(&config.db_url).await {
             Ok(_) => {}
             Err(error) => {
                 panic!("{}", error)
@@ -85,68 +179,288 @@ async fn main() {
         }
     }
 
-    let pool = SqlitePool::connect(DB_URL).await.unwrap();
+    let pool = SqlitePool::connect(&config.db_url).await.unwrap();
     init_db(&pool).await;
+    admin::set_commands(&bot, &pool, &config).await;
+    let directory: SharedDirectory = Arc::new(RwLock::new(Directory::load(&config).await));
 
-    let timers = tokio::task::spawn({
-        let bot = bot.clone();
-        let pool = pool.clone();
+    tokio::task::spawn({
+        let config = config.clone();
+        let directory = directory.clone();
 
         async move {
-            let mut interval = time::interval(Duration::from_secs(60));
+            let mut interval = time::interval(config.directory_ttl);
+            interval.tick().await;
             loop {
                 interval.tick().await;
-                // let users = USERS.lock().unwrap().clone();
-                let users = get_all_users(&pool).await.unwrap();
-                let now = Local::now().time();
-                for user in users {
-                    let diff = (now - user.notification_time).num_minutes();
-                    if diff == 0 && now > user.notification_time {
-                        let events = get_events(user.city, user.tags, user.events_interval).await;
-                        let mut total = events.len();
-                        let mut offset = 0;
-                        let step = 10;
-                        while total > 0 {
-                            let mut output = String::new();
-                            for event in &events[offset..offset + step] {
-                                output = format!(
-                                    "{output}\n{}\nhttps://afisha.yandex.ru/{}/n",
-                                    event.title, event.url
-                                );
-                            }
-                            bot.send_message(ChatId(user.tg_id.try_into().unwrap()), output)
-                                .await
-                                .unwrap();
-                            offset += step;
-                            total -= step;
-                        }
-                    }
-                }
+                let fresh = Directory::load(&config).await;
+                *directory.write().unwrap() = fresh;
             }
         }
     });
 
-    Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![InMemStorage::<State>::new(), pool])
-        .enable_ctrlc_handler()
-        .build()
-        .dispatch()
+    let shutdown = CancellationToken::new();
+    let shutdown_timeout = config.shutdown_timeout;
+    tokio::task::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            wait_for_signal().await;
+            log::info!("Shutting down...");
+            shutdown.cancel();
+        }
+    });
+
+    let scheduler = tokio::task::spawn(run_scheduler(
+        pool.clone(),
+        config.clone(),
+        shutdown.clone(),
+    ));
+    // Digests and broadcasts count against the same Telegram limit.
+    let limiter = RateLimiter::per_second(TELEGRAM_MESSAGES_PER_SECOND);
+    let deliveries = tokio::task::spawn(outbox::run_worker(
+        bot.clone(),
+        pool.clone(),
+        limiter.clone(),
+        shutdown.clone(),
+    ));
+    let broadcasts = tokio::task::spawn(broadcast::run_worker(
+        bot.clone(),
+        pool.clone(),
+        limiter,
+        shutdown.clone(),
+    ));
+    tokio::task::spawn(health::serve(
+        pool.clone(),
+        config.clone(),
+        shutdown.clone(),
+    ));
+
+    let webhook = webhook_options(&config);
+    let mut dispatcher = Dispatcher::builder(bot.clone(), schema())
+        .dependencies(dptree::deps![
+            InMemStorage::<State>::new(),
+            pool,
+            directory,
+            config
+        ])
+        .build();
+    tokio::task::spawn({
+        let dispatcher = dispatcher.shutdown_token();
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.cancelled().await;
+            if let Ok(stopped) = dispatcher.shutdown() {
+                stopped.await;
+            }
+        }
+    });
+    match webhook {
+        Some(options) => {
+            log::info!("Receiving updates via webhook at {}", options.url);
+            let listener = match webhooks::axum(bot, options).await {
+                Ok(listener) => listener,
+                Err(error) => {
+                    log::error!("Failed to set up webhook: {error}");
+                    std::process::exit(1);
+                }
+            };
+            dispatcher
+                .dispatch_with_listener(
+                    listener,
+                    LoggingErrorHandler::with_custom_text("An error from the update listener"),
+                )
+                .await;
+        }
+        None => {
+            log::info!("No webhook_url configured, using long polling");
+            dispatcher.dispatch().await;
+        }
+    }
+    shutdown.cancel();
+    let workers = async {
+        scheduler.await.unwrap();
+        deliveries.await.unwrap();
+        broadcasts.await.unwrap();
+    };
+    if time::timeout(shutdown_timeout, workers).await.is_err() {
+        log::warn!("Workers still running after {shutdown_timeout:?}, stopping anyway");
+    }
+    log::info!("Stopped");
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.unwrap();
+}
+
+/// Queues due digests every `config.tick` until `shutdown`, then gives digests still
+/// being rendered `config.shutdown_timeout` to finish. Those still running keep their
+/// `next_fire_at`, so the next start queues them again; the outbox key drops any that
+/// were queued after all.
+async fn run_scheduler(pool: SqlitePool, config: Arc<Config>, shutdown: CancellationToken) {
+    let deliveries = TaskTracker::new();
+    let sending = Arc::new(Semaphore::new(config.concurrency));
+    let deliver = |user: User, date: NaiveDate, permit: OwnedSemaphorePermit| {
+        let (pool, config) = (pool.clone(), config.clone());
+        deliveries.spawn(async move {
+            queue_digest(&pool, &config, user, date).await;
+            drop(permit);
+        });
+    };
+
+    let mut scheduler = Scheduler::new(SystemClock, config.timezone, config.tick);
+    scheduler
+        .run(
+            &shutdown,
+            |now| {
+                let pool = &pool;
+                async move {
+                    get_due_users(pool, now).await.unwrap_or_else(|error| {
+                        log::error!("Failed to load due users: {error}");
+                        Vec::new()
+                    })
+                }
+            },
+            |schedule| {
+                let pool = &pool;
+                async move {
+                    if let Err(error) = set_next_fire_at(pool, &schedule).await {
+                        log::error!("Failed to reschedule {} users: {error}", schedule.len());
+                    }
+                }
+            },
+            |user, date| {
+                let (deliver, sending, shutdown) = (&deliver, sending.clone(), &shutdown);
+                async move {
+                    tokio::select! {
+                        permit = sending.acquire_owned() => deliver(user, date, permit.unwrap()),
+                        _ = shutdown.cancelled() => {}
+                    }
+                }
+            },
+        )
         .await;
-    timers.await.unwrap();
+
+    deliveries.close();
+    if time::timeout(config.shutdown_timeout, deliveries.wait())
+        .await
+        .is_err()
+    {
+        log::warn!(
+            "Digests still sending after {:?}, they will be queued again on the next start",
+            config.shutdown_timeout
+        );
+    }
+}
+
+fn init_tracing(config: &Config) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.log_level))
+        .with_span_events(FmtSpan::CLOSE);
+    match config.log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+fn webhook_options(config: &Config) -> Option<webhooks::Options> {
+    let mut options = webhooks::Options::new(config.webhook_address, config.webhook()?);
+    if let Some(secret) = &config.webhook_secret {
+        options = options.secret_token(secret.clone());
+    }
+    Some(options)
+}
+
+/// Renders the digest `user` gets on `date` and hands it to the outbox worker.
+#[tracing::instrument(
+    name = "digest",
+    skip_all,
+    fields(user = user.tg_id, city = %user.city, categories = ?user.categories, events, messages)
+)]
+async fn queue_digest(pool: &SqlitePool, config: &Config, mut user: User, date: NaiveDate) {
+    let tg_id = user.tg_id;
+    let welcome_back = user.paused_until.is_some();
+    let messages = match digest::build(config, &user, date).await {
+        Ok(messages) => messages,
+        // Nothing is recorded, so a restart within the grace period still sends it.
+        Err(error) => {
+            log::error!("Failed to build digest for {tg_id}: {error}");
+            metrics().digests_failed.inc();
+            return;
+        }
+    };
+    match outbox::enqueue_digest(pool, tg_id, date, messages, &digest_keyboard()).await {
+        Ok(queued) => tracing::info!(queued, "digest queued"),
+        Err(error) => {
+            log::error!("Failed to queue digest for {tg_id}: {error}");
+            metrics().digests_failed.inc();
+            return;
+        }
+    }
+    let now = Utc::now();
+    user.last_digest_at = Some(now);
+    let next_fire_at = scheduler::next_fire_at(&user, now, config.timezone);
+    if let Err(error) = set_last_digest_at(pool, tg_id, now, next_fire_at).await {
+        log::error!("Failed to record digest for {tg_id}: {error}");
+    }
+    if welcome_back {
+        if let Err(error) = set_paused_until(pool, tg_id, None).await {
+            log::error!("Failed to clear pause for {tg_id}: {error}");
+        }
+    }
+}
+
+const SNOOZE_TOMORROW: &str = "snooze:tomorrow";
+const SNOOZE_WEEK: &str = "snooze:week";
+
+fn digest_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("Пропустить завтра", SNOOZE_TOMORROW),
+        InlineKeyboardButton::callback("Пауза на неделю", SNOOZE_WEEK),
+    ]])
 }
 
 fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
     use dptree::case;
 
-    let command_handler = teloxide::filter_command::<Command, _>().branch(
-        case![State::Start]
-            .branch(case![Command::Help].endpoint(cmd_help))
-            .branch(case![Command::Start].endpoint(cmd_start))
-            .branch(case![Command::Info].endpoint(cmd_info))
-            .branch(case![Command::Edit { parameter }].endpoint(cmd_edit)),
-    );
+    let command_handler = teloxide::filter_command::<Command, _>()
+        .branch(case![Command::Cancel].endpoint(cmd_cancel))
+        .branch(
+            case![State::Start]
+                .branch(case![Command::Help].endpoint(cmd_help))
+                .branch(case![Command::Start].endpoint(cmd_start))
+                .branch(case![Command::Info].endpoint(cmd_info))
+                .branch(case![Command::Settings].endpoint(cmd_settings))
+                .branch(case![Command::Pause].endpoint(cmd_pause))
+                .branch(case![Command::Resume].endpoint(cmd_resume))
+                .branch(case![Command::Stop].endpoint(cmd_stop))
+                .branch(case![Command::Delete].endpoint(cmd_delete))
+                .branch(case![Command::Snooze { period }].endpoint(cmd_snooze))
+                .branch(case![Command::Edit { args }].endpoint(cmd_edit))
+                .branch(case![Command::Preview { tg_id }].endpoint(cmd_preview)),
+        );
+    let admin_command_handler = teloxide::filter_command::<AdminCommand, _>()
+        .filter_async(
+            |msg: Message, pool: SqlitePool, config: Arc<Config>| async move {
+                admin::is_admin(&pool, &config, msg.chat.id).await
+            },
+        )
+        .branch(case![AdminCommand::Stats].endpoint(admin::cmd_stats))
+        .branch(case![AdminCommand::User { tg_id }].endpoint(admin::cmd_user))
+        .branch(case![AdminCommand::Broadcast { text }].endpoint(admin::cmd_broadcast))
+        .branch(case![AdminCommand::Reload].endpoint(admin::cmd_reload));
     let message_handler = Update::filter_message()
         .branch(command_handler)
+        .branch(admin_command_handler)
         .branch(case![State::City].endpoint(receive_city))
         .branch(case![State::Categories { city }].endpoint(receive_categories))
         .branch(
@@ -160,15 +474,139 @@ fn schema() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>>
             }]
             .endpoint(receive_events_interval),
         )
-        .branch(case![State::EditCity].endpoint(receive_edit_city))
-        .branch(case![State::EditCategories].endpoint(receive_edit_categories))
-        .branch(case![State::EditNotificationTime].endpoint(receive_edit_notification_time))
-        .branch(case![State::EditEventsInterval].endpoint(receive_edit_events_interval));
-    dialogue::enter::<Update, InMemStorage<State>, State, _>().branch(message_handler)
+        .branch(case![State::EditCity { menu }].endpoint(receive_edit_city))
+        .branch(case![State::EditCategories { menu }].endpoint(receive_edit_categories))
+        .branch(
+            case![State::EditNotificationTime { menu }].endpoint(receive_edit_notification_time),
+        )
+        .branch(case![State::EditEventsInterval { menu }].endpoint(receive_edit_events_interval))
+        .branch(case![State::EditMaxPrice { menu }].endpoint(receive_edit_max_price));
+    let callback_handler = Update::filter_callback_query()
+        .branch(
+            dptree::filter(|q: CallbackQuery| has_prefix(&q, "delete:"))
+                .endpoint(receive_delete_confirmation),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| has_prefix(&q, "snooze:"))
+                .endpoint(receive_snooze_button),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| has_prefix(&q, TIME_PREFIX))
+                .branch(
+                    case![State::NotificationTime { city, categories }]
+                        .endpoint(receive_time_button),
+                )
+                .branch(
+                    case![State::EditNotificationTime { menu }].endpoint(receive_edit_time_button),
+                ),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| has_prefix(&q, SETTINGS_PREFIX))
+                .endpoint(receive_settings_button),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| has_prefix(&q, AGE_PREFIX))
+                .endpoint(receive_age_button),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| has_prefix(&q, PREVIEW_PREFIX))
+                .endpoint(receive_preview_button),
+        )
+        .branch(
+            dptree::filter(|q: CallbackQuery| has_prefix(&q, "broadcast:")).branch(
+                case![State::ConfirmBroadcast { text, filter }]
+                    .endpoint(admin::receive_broadcast_confirmation),
+            ),
+        )
+        .endpoint(receive_stale_button);
+    dialogue::enter::<Update, InMemStorage<State>, State, _>()
+        .chain(trace_update())
+        .inspect(|state: State| {
+            metrics()
+                .dialogue_states
+                .with_label_values(&[state.name()])
+                .inc();
+        })
+        .branch(message_handler)
+        .branch(callback_handler)
+}
+
+/// Runs the rest of the handler chain inside an `update` span.
+fn trace_update() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
+    dptree::from_fn(|deps: DependencyMap, cont| async move {
+        let update: Arc<Update> = deps.get();
+        let state: Arc<State> = deps.get();
+        let action = match &update.kind {
+            UpdateKind::Message(msg) => msg
+                .text()
+                .and_then(|text| text.split_whitespace().next())
+                .filter(|word| word.starts_with('/'))
+                .map(str::to_string),
+            UpdateKind::CallbackQuery(q) => q.data.clone(),
+            _ => None,
+        };
+        let span = tracing::info_span!(
+            "update",
+            id = update.id,
+            chat_id = update.chat().map(|chat| chat.id.0),
+            state = state.name(),
+            action,
+        );
+        cont(deps).instrument(span).await
+    })
+}
+
+fn has_prefix(q: &CallbackQuery, prefix: &str) -> bool {
+    q.data
+        .as_deref()
+        .is_some_and(|data| data.starts_with(prefix))
+}
+
+/// Buttons of a picker whose dialogue is over, e.g. after /cancel. Answering stops
+/// the client's spinner.
+async fn receive_stale_button(bot: Bot, q: CallbackQuery) -> HandlerResult {
+    bot.answer_callback_query(q.id)
+        .text("Это меню устарело.")
+        .await?;
+    Ok(())
+}
+
+/// Nothing is written to the database until a dialogue finishes, so dropping the
+/// state is enough to keep the previous settings.
+fn cancel_reply(state: &State, has_settings: bool) -> &'static str {
+    match state {
+        State::Start => "Нечего отменять.",
+        State::City
+        | State::Categories { .. }
+        | State::NotificationTime { .. }
+        | State::EventsInterval { .. } => {
+            if has_settings {
+                "Настройка отменена, прежние параметры сохранены."
+            } else {
+                "Настройка отменена. Чтобы начать заново, отправьте /start."
+            }
+        }
+        State::EditCity { .. }
+        | State::EditCategories { .. }
+        | State::EditNotificationTime { .. }
+        | State::EditEventsInterval { .. }
+        | State::EditMaxPrice { .. } => "Изменение отменено, прежние параметры сохранены.",
+        State::ConfirmBroadcast { .. } => "Рассылка отменена.",
+    }
 }
 
-async fn cmd_cancel(bot: Bot, msg: Message, dialogue: MyDialogue) -> HandlerResult {
-    dialogue.update(State::Start).await?;
+async fn cmd_cancel(
+    bot: Bot,
+    msg: Message,
+    dialogue: MyDialogue,
+    state: State,
+    pool: SqlitePool,
+) -> HandlerResult {
+    let has_settings = get_user(&pool, msg.chat.id.0 as u64).await.is_some();
+    dialogue.exit().await?;
+    bot.send_message(msg.chat.id, cancel_reply(&state, has_settings))
+        .reply_markup(KeyboardRemove::new())
+        .await?;
     Ok(())
 }
 
@@ -178,103 +616,475 @@ async fn cmd_help(bot: Bot, msg: Message) -> HandlerResult {
     Ok(())
 }
 
-async fn cmd_info(bot: Bot, msg: Message, pool: SqlitePool) -> HandlerResult {
-    let user = get_user(&pool, msg.chat.id.0 as u64).await.unwrap();
+/// `timezone` is the bot's, which pause times are shown in; `rubrics` of the user's
+/// city name the categories.
+fn user_summary(user: &User, timezone: chrono_tz::Tz, rubrics: &[Rubric]) -> String {
     let tg_id = user.tg_id;
-    let city = user.city;
-    let categories = user.tags;
-    let categories_to_print = categories.join(" ");
+    let city = &user.city;
+    let categories_to_print = category::names(&user.categories, rubrics);
     let notification_time = user.notification_time;
-    let events_interval = user.events_interval;
+    let events_interval = &user.events_interval;
+    let mut text = format!(
+        "Вы выбрали\nВаше id: {tg_id}\nВаш город: {city}\nКатегории: {categories_to_print}\nВремя оповещений: {notification_time}\nИнтервал предстоящих событий: {events_interval}"
+    );
+    text = format!("{text}\nЦена: {}", price_limit(user));
+    if user.free_only {
+        text = format!("{text}, только бесплатные");
+    }
+    text = format!("{text}\nВозраст: {}", user.age_filter);
+    if let Some(until) = user.paused_until.filter(|until| *until > Utc::now()) {
+        text = format!(
+            "{text}\nПауза до: {}",
+            until.with_timezone(&timezone).format("%d.%m.%Y %H:%M")
+        );
+    }
+    text
+}
+
+async fn cmd_info(bot: Bot, msg: Message, pool: SqlitePool, config: Arc<Config>) -> HandlerResult {
+    let Some(user) = get_user(&pool, msg.chat.id.0 as u64).await else {
+        bot.send_message(msg.chat.id, "Сначала настройте бота командой /start.")
+            .await?;
+        return Ok(());
+    };
+    let rubrics = directory::cached_categories(&pool, &user.city).await;
+    bot.send_message(msg.chat.id, user_summary(&user, config.timezone, &rubrics))
+        .await?;
+    Ok(())
+}
+
+async fn cmd_start(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: SqlitePool,
+) -> HandlerResult {
+    if let Some(user) = get_user(&pool, msg.chat.id.0 as u64).await {
+        if user.blocked_at.is_some() {
+            reactivate_user(&pool, user.tg_id).await?;
+        }
+    }
+    bot.send_message(msg.chat.id, "Давайте начнем! Из какого вы города?")
+        .await?;
+    dialogue.update(State::City).await?;
+    Ok(())
+}
+
+async fn cmd_pause(bot: Bot, msg: Message, pool: SqlitePool) -> HandlerResult {
+    if get_user(&pool, msg.chat.id.0 as u64).await.is_none() {
+        bot.send_message(msg.chat.id, "Сначала настройте бота командой /start.")
+            .await?;
+        return Ok(());
+    }
+    set_paused(&pool, msg.chat.id.0 as u64, true).await?;
+    bot.send_message(
+        msg.chat.id,
+        "Рассылка приостановлена. Чтобы возобновить, отправьте /resume.",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn cmd_resume(bot: Bot, msg: Message, pool: SqlitePool) -> HandlerResult {
+    if get_user(&pool, msg.chat.id.0 as u64).await.is_none() {
+        bot.send_message(msg.chat.id, "Сначала настройте бота командой /start.")
+            .await?;
+        return Ok(());
+    }
+    resume_user(&pool, msg.chat.id.0 as u64).await?;
+    bot.send_message(msg.chat.id, "Рассылка возобновлена.")
+        .await?;
+    Ok(())
+}
+
+async fn cmd_stop(bot: Bot, msg: Message, pool: SqlitePool) -> HandlerResult {
+    if get_user(&pool, msg.chat.id.0 as u64).await.is_none() {
+        bot.send_message(msg.chat.id, "Вы и так не подписаны на рассылку.")
+            .await?;
+        return Ok(());
+    }
+    set_active(&pool, msg.chat.id.0 as u64, false).await?;
+    bot.send_message(
+        msg.chat.id,
+        "Рассылка отключена, настройки сохранены. Чтобы включить её снова, отправьте /resume.",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn cmd_snooze(
+    bot: Bot,
+    msg: Message,
+    period: String,
+    pool: SqlitePool,
+    config: Arc<Config>,
+) -> HandlerResult {
+    if get_user(&pool, msg.chat.id.0 as u64).await.is_none() {
+        bot.send_message(msg.chat.id, "Сначала настройте бота командой /start.")
+            .await?;
+        return Ok(());
+    }
+    let until = match parse_snooze(&period, Utc::now().with_timezone(&config.timezone)) {
+        Ok(until) => until,
+        Err(error) => {
+            bot.send_message(msg.chat.id, format!("{error}\n{SNOOZE_HINT}"))
+                .await?;
+            return Ok(());
+        }
+    };
+    set_paused_until(&pool, msg.chat.id.0 as u64, Some(until)).await?;
     bot.send_message(
         msg.chat.id,
         format!(
-            "Вы выбрали\nВаше id: {tg_id}\nВаш город: {city}\nКатегории: {categories_to_print}\nВремя оповещений: {notification_time}\nИнтервал предстоящих событий: {events_interval}"
+            "Рассылка на паузе до {}. Чтобы возобновить раньше, отправьте /resume.",
+            until
+                .with_timezone(&config.timezone)
+                .format("%d.%m.%Y %H:%M")
         ),
     )
     .await?;
     Ok(())
 }
 
-async fn cmd_start(
+async fn receive_snooze_button(
+    bot: Bot,
+    q: CallbackQuery,
+    pool: SqlitePool,
+    config: Arc<Config>,
+) -> HandlerResult {
+    let now = Utc::now().with_timezone(&config.timezone);
+    let until = match q.data.as_deref() {
+        // Resuming at the start of the day after tomorrow skips exactly one digest.
+        Some(SNOOZE_TOMORROW) => config
+            .timezone
+            .from_local_datetime(
+                &(now.date_naive() + chrono::Duration::days(2)).and_time(NaiveTime::MIN),
+            )
+            .earliest(),
+        Some(SNOOZE_WEEK) => Some(now + chrono::Duration::weeks(1)),
+        _ => None,
+    };
+    let Some(until) = until else {
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+    set_paused_until(&pool, q.from.id.0, Some(until.with_timezone(&Utc))).await?;
+    bot.answer_callback_query(q.id)
+        .text(format!("Пауза до {}", until.format("%d.%m.%Y %H:%M")))
+        .await?;
+    Ok(())
+}
+
+const DELETE_CONFIRM: &str = "delete:confirm";
+const DELETE_CANCEL: &str = "delete:cancel";
+
+async fn cmd_delete(bot: Bot, msg: Message) -> HandlerResult {
+    let keyboard = InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("Да, удалить", DELETE_CONFIRM),
+        InlineKeyboardButton::callback("Отмена", DELETE_CANCEL),
+    ]]);
+    bot.send_message(
+        msg.chat.id,
+        "Удалить все ваши данные? Настройки и история будут стёрты без возможности восстановления.",
+    )
+    .reply_markup(keyboard)
+    .await?;
+    Ok(())
+}
+
+async fn receive_delete_confirmation(
     bot: Bot,
     dialogue: MyDialogue,
-    msg: Message
+    q: CallbackQuery,
+    pool: SqlitePool,
 ) -> HandlerResult {
-    bot.send_message(msg.chat.id, "Давайте начнем! Из какого вы города?")
+    bot.answer_callback_query(q.id).await?;
+    let Some(message) = q.message else {
+        return Ok(());
+    };
+    let text = match q.data.as_deref() {
+        Some(DELETE_CONFIRM) => {
+            delete_user(&pool, message.chat.id.0 as u64).await?;
+            dialogue.exit().await?;
+            "Все ваши данные удалены. Чтобы начать заново, отправьте /start."
+        }
+        Some(DELETE_CANCEL) => "Удаление отменено.",
+        _ => return Ok(()),
+    };
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .await?;
+    Ok(())
+}
+
+const SETTINGS_TEXT: &str = "Ваши настройки. Нажмите на параметр, чтобы изменить его.";
+
+async fn cmd_settings(bot: Bot, msg: Message, pool: SqlitePool) -> HandlerResult {
+    let Some(user) = get_user(&pool, msg.chat.id.0 as u64).await else {
+        bot.send_message(msg.chat.id, "Сначала настройте бота командой /start.")
+            .await?;
+        return Ok(());
+    };
+    let rubrics = directory::cached_categories(&pool, &user.city).await;
+    bot.send_message(msg.chat.id, SETTINGS_TEXT)
+        .reply_markup(settings_keyboard(&user, &rubrics))
         .await?;
-    dialogue.update(State::City).await?;
     Ok(())
 }
 
-const PARAMETERS: [&str; 4] = [
-    "city",
-    "categories",
-    "notification_time",
-    "events_intervall",
-];
+/// Shows the new values in the settings menu. The change is saved by then, so a
+/// failed edit is only logged and the caller still confirms it.
+async fn refresh_settings_menu(bot: &Bot, pool: &SqlitePool, chat_id: ChatId, menu: MessageId) {
+    let Some(user) = get_user(pool, chat_id.0 as u64).await else {
+        return;
+    };
+    let rubrics = directory::cached_categories(pool, &user.city).await;
+    let result = bot
+        .edit_message_text(chat_id, menu, SETTINGS_TEXT)
+        .reply_markup(settings_keyboard(&user, &rubrics))
+        .await;
+    match result {
+        // The setting was changed to the value it already had.
+        Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+        Err(error) => log::warn!("Failed to refresh settings menu in {chat_id}: {error}"),
+    }
+}
 
-async fn cmd_edit(
+async fn cmd_preview(
     bot: Bot,
     msg: Message,
-    parameter: String,
-    dialogue: MyDialogue,
+    tg_id: String,
+    pool: SqlitePool,
+    config: Arc<Config>,
 ) -> HandlerResult {
-    match parameter.as_str() {
-        "city" => {
-            bot.send_message(msg.chat.id, "Введите новый город").await?;
-            dialogue.update(State::EditCity).await?;
+    let tg_id = tg_id.trim();
+    let target = if tg_id.is_empty() {
+        msg.chat.id.0 as u64
+    } else if admin::is_admin(&pool, &config, msg.chat.id).await {
+        match tg_id.parse() {
+            Ok(tg_id) => tg_id,
+            Err(_) => {
+                bot.send_message(msg.chat.id, "Использование: /preview <tg_id>")
+                    .await?;
+                return Ok(());
+            }
         }
-        "categories" => {
-            bot.send_message(msg.chat.id, "Введите новые категории")
+    } else {
+        bot.send_message(msg.chat.id, "Эта команда доступна только администраторам.")
+            .await?;
+        return Ok(());
+    };
+    let Some(user) = get_user(&pool, target).await else {
+        bot.send_message(msg.chat.id, "Пользователь не найден. Настройка: /start.")
+            .await?;
+        return Ok(());
+    };
+    let Some((date, slot)) = scheduler::next_slot(&user, Utc::now(), config.timezone) else {
+        bot.send_message(
+            msg.chat.id,
+            "Следующий дайджест не запланирован: рассылка остановлена или на паузе.",
+        )
+        .await?;
+        return Ok(());
+    };
+    let Ok(messages) = digest::build(&config, &user, date).await else {
+        bot.send_message(msg.chat.id, AFISHA_UNAVAILABLE).await?;
+        return Ok(());
+    };
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "Дайджест для {target} на {}:",
+            slot.with_timezone(&config.timezone)
+                .format("%d.%m.%Y %H:%M")
+        ),
+    )
+    .await?;
+    if messages.is_empty() {
+        bot.send_message(
+            msg.chat.id,
+            "Событий не нашлось, дайджест не будет отправлен.",
+        )
+        .await?;
+    }
+    for output in messages {
+        bot.send_message(msg.chat.id, output).await?;
+    }
+    Ok(())
+}
+
+// `get_events` has already logged the error.
+const AFISHA_UNAVAILABLE: &str = "Не удалось получить события с Афиши, попробуйте позже.";
+
+async fn confirm_change(bot: &Bot, chat_id: ChatId, text: String) -> HandlerResult {
+    bot.send_message(chat_id, text)
+        .reply_markup(preview_keyboard())
+        .await?;
+    Ok(())
+}
+
+/// Renders tomorrow's digest under the current settings right away.
+async fn receive_preview_button(
+    bot: Bot,
+    q: CallbackQuery,
+    pool: SqlitePool,
+    config: Arc<Config>,
+) -> HandlerResult {
+    bot.answer_callback_query(q.id).await?;
+    let Some(message) = q.message else {
+        return Ok(());
+    };
+    let Some(user) = get_user(&pool, message.chat.id.0 as u64).await else {
+        return Ok(());
+    };
+    let tomorrow =
+        Utc::now().with_timezone(&config.timezone).date_naive() + chrono::Duration::days(1);
+    let Ok(messages) = digest::build(&config, &user, tomorrow).await else {
+        bot.send_message(message.chat.id, AFISHA_UNAVAILABLE)
+            .await?;
+        return Ok(());
+    };
+    if messages.is_empty() {
+        bot.send_message(
+            message.chat.id,
+            "На завтра по вашим настройкам событий не нашлось.",
+        )
+        .await?;
+        return Ok(());
+    }
+    bot.send_message(message.chat.id, "Так будет выглядеть дайджест на завтра:")
+        .await?;
+    for output in messages {
+        bot.send_message(message.chat.id, output).await?;
+    }
+    Ok(())
+}
+
+async fn receive_settings_button(
+    bot: Bot,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    pool: SqlitePool,
+    config: Arc<Config>,
+) -> HandlerResult {
+    bot.answer_callback_query(q.id).await?;
+    let button = q.data.as_deref().and_then(parse_settings_button);
+    let (Some(message), Some(button)) = (q.message, button) else {
+        return Ok(());
+    };
+    let menu = message.id;
+    match button {
+        SettingsButton::City => {
+            bot.send_message(message.chat.id, "Введите новый город")
                 .await?;
-            dialogue.update(State::EditCategories).await?;
+            dialogue.update(State::EditCity { menu }).await?;
         }
-        "notification_time" => {
-            bot.send_message(msg.chat.id, "Введите новое время для уведомлений")
-                .await?;
-            dialogue.update(State::EditNotificationTime).await?;
+        SettingsButton::Categories => {
+            let categories = user_categories(&pool, &config, message.chat.id).await;
+            bot.send_message(
+                message.chat.id,
+                format!(
+                    "Введите новые категории через запятую: {}",
+                    category::rubric_names(&categories)
+                ),
+            )
+            .await?;
+            dialogue.update(State::EditCategories { menu }).await?;
         }
-        "events_interval" => {
-            bot.send_message(msg.chat.id, "Введите новые интервалы")
+        SettingsButton::NotificationTime => {
+            bot.send_message(
+                message.chat.id,
+                format!("Выберите новое время для уведомлений или введите его. {TIME_HINT}"),
+            )
+            .reply_markup(hour_keyboard())
+            .await?;
+            dialogue
+                .update(State::EditNotificationTime { menu })
                 .await?;
-            dialogue.update(State::EditEventsInterval).await?;
         }
-        _ => {
-            bot.send_message(msg.chat.id, "Неправильный параметр")
+        SettingsButton::EventsInterval => {
+            bot.send_message(
+                message.chat.id,
+                format!("Введите новый интервал.\n{INTERVAL_HINT}"),
+            )
+            .await?;
+            dialogue.update(State::EditEventsInterval { menu }).await?;
+        }
+        SettingsButton::MaxPrice => {
+            bot.send_message(
+                message.chat.id,
+                format!("Введите максимальную цену билета в рублях.\n{PRICE_HINT}"),
+            )
+            .await?;
+            dialogue.update(State::EditMaxPrice { menu }).await?;
+        }
+        SettingsButton::FreeOnly => {
+            let Some(user) = get_user(&pool, message.chat.id.0 as u64).await else {
+                return Ok(());
+            };
+            set_free_only(&pool, user.tg_id, !user.free_only).await?;
+            refresh_settings_menu(&bot, &pool, message.chat.id, menu).await;
+            let confirmation = if user.free_only {
+                "Теперь в дайджесте все события."
+            } else {
+                "Теперь в дайджесте только бесплатные события."
+            };
+            confirm_change(&bot, message.chat.id, confirmation.into()).await?;
+        }
+        SettingsButton::AgeFilter => {
+            bot.edit_message_reply_markup(message.chat.id, menu)
+                .reply_markup(age_keyboard())
                 .await?;
         }
     }
     Ok(())
 }
 
+/// A choice from [`age_keyboard`], which replaces the settings keyboard in the menu.
+async fn receive_age_button(bot: Bot, q: CallbackQuery, pool: SqlitePool) -> HandlerResult {
+    bot.answer_callback_query(q.id).await?;
+    let age_filter = q.data.as_deref().and_then(parse_age_button);
+    let (Some(message), Some(age_filter)) = (q.message, age_filter) else {
+        return Ok(());
+    };
+    set_age_filter(&pool, message.chat.id.0 as u64, age_filter).await?;
+    refresh_settings_menu(&bot, &pool, message.chat.id, message.id).await;
+    confirm_change(
+        &bot,
+        message.chat.id,
+        format!("Возраст изменён: {age_filter}"),
+    )
+    .await?;
+    Ok(())
+}
+
 async fn receive_edit_city(
     bot: Bot,
     dialogue: MyDialogue,
+    menu: MessageId,
     msg: Message,
     pool: SqlitePool,
+    directory: SharedDirectory,
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot, msg, dialogue).await?;
-                return Ok(());
-            }
+            let city = resolve_city(&directory, text);
             update_user(
                 &pool,
                 UserFilter {
                     id: None,
                     tg_id: None,
-                    city: Some(text.into()),
-                    tags: None,
+                    city: Some(city.clone()),
+                    categories: None,
                     notification_time: None,
                     events_interval: None,
                 },
-                msg.chat.id.0 as u64     
+                msg.chat.id.0 as u64,
             )
             .await
             .unwrap();
             dialogue.exit().await?;
+            refresh_settings_menu(&bot, &pool, msg.chat.id, menu).await;
+            confirm_change(&bot, msg.chat.id, format!("Город изменён: {city}")).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "Отправьте ваш город.")
@@ -284,49 +1094,54 @@ async fn receive_edit_city(
     Ok(())
 }
 
+/// Categories offered in the city of the user in `chat_id`.
+async fn user_categories(pool: &SqlitePool, config: &Config, chat_id: ChatId) -> Vec<Rubric> {
+    let city = get_user(pool, chat_id.0 as u64)
+        .await
+        .map(|user| user.city)
+        .unwrap_or_default();
+    directory::categories(pool, config, &city).await
+}
+
 async fn receive_edit_categories(
     bot: Bot,
     dialogue: MyDialogue,
+    menu: MessageId,
     msg: Message,
     pool: SqlitePool,
+    config: Arc<Config>,
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot, msg, dialogue).await?;
-                return Ok(());
-            }
-            let parts = text.split(',');
-            let mut categories = Vec::new();
-            for part in parts {
-                match CATEGORIES.iter().find(|el| **el == part.trim()) {
-                    Some(_text) => {}
-                    None => {
-                        bot.send_message(msg.chat.id, format!("{part} не категория."))
-                            .await?;
-                        dialogue
-                            .update(State::EditCategories)
-                            .await?;
-                        return Ok(());
-                    }
-                };
-                categories.push(part.to_string());
-            }
+            let known = user_categories(&pool, &config, msg.chat.id).await;
+            let categories = match parse_categories(text, &known) {
+                Ok(categories) => categories,
+                Err(error) => {
+                    bot.send_message(msg.chat.id, error.to_string()).await?;
+                    return Ok(());
+                }
+            };
+            let confirmation = format!(
+                "Категории изменены: {}",
+                category::names(&categories, &known)
+            );
             update_user(
                 &pool,
                 UserFilter {
                     id: None,
                     tg_id: None,
                     city: None,
-                    tags: Some(categories),
+                    categories: Some(categories),
                     notification_time: None,
                     events_interval: None,
                 },
-                msg.chat.id.0 as u64     
+                msg.chat.id.0 as u64,
             )
             .await
             .unwrap();
             dialogue.exit().await?;
+            refresh_settings_menu(&bot, &pool, msg.chat.id, menu).await;
+            confirm_change(&bot, msg.chat.id, confirmation).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "Отправьте ваш город.")
@@ -339,51 +1154,22 @@ async fn receive_edit_categories(
 async fn receive_edit_notification_time(
     bot: Bot,
     dialogue: MyDialogue,
+    menu: MessageId,
     msg: Message,
     pool: SqlitePool,
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot, msg, dialogue).await?;
-                return Ok(());
-            }
-            let parts: Vec<&str> = text.split(':').collect();
-            let hour = match parts[0].parse::<u32>() {
-                Ok(num) => num,
-                Err(_) => {
-                    dialogue.update(State::EditNotificationTime).await?;
-                    return Ok(())
-                },
-            };
-            let min = match parts[1].parse::<u32>() {
-                Ok(num) => num,
-                Err(_) => {
-                    dialogue.update(State::EditNotificationTime).await?;
-                    return Ok(())
-                },
+            let notification_time = match parse_time(text) {
+                Ok(notification_time) => notification_time,
+                Err(error) => {
+                    bot.send_message(msg.chat.id, format!("{error} {TIME_HINT}"))
+                        .await?;
+                    return Ok(());
+                }
             };
-            let notification_time = NaiveTime::from_hms_opt(
-                hour,
-                min,
-                0,
-            )
-            .unwrap();
-            update_user(
-                &pool,
-                UserFilter {
-                    id: None,
-                    tg_id: None,
-                    city: None,
-                    tags: None,
-                    notification_time: Some(notification_time),
-                    events_interval: None,
-                },
-                msg.chat.id.0 as u64     
-            )
-            .await
-            .unwrap();
-            dialogue.exit().await?;
+            save_notification_time(&bot, &dialogue, &pool, msg.chat.id, menu, notification_time)
+                .await?;
         }
         None => {
             bot.send_message(msg.chat.id, "Отправьте ваш город.")
@@ -393,34 +1179,76 @@ async fn receive_edit_notification_time(
     Ok(())
 }
 
+async fn save_notification_time(
+    bot: &Bot,
+    dialogue: &MyDialogue,
+    pool: &SqlitePool,
+    chat_id: ChatId,
+    menu: MessageId,
+    notification_time: NaiveTime,
+) -> HandlerResult {
+    update_user(
+        pool,
+        UserFilter {
+            id: None,
+            tg_id: None,
+            city: None,
+            categories: None,
+            notification_time: Some(notification_time),
+            events_interval: None,
+        },
+        chat_id.0 as u64,
+    )
+    .await?;
+    dialogue.exit().await?;
+    refresh_settings_menu(bot, pool, chat_id, menu).await;
+    confirm_change(
+        bot,
+        chat_id,
+        format!(
+            "Время оповещений изменено: {}",
+            notification_time.format("%H:%M")
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
 async fn receive_edit_events_interval(
     bot: Bot,
     dialogue: MyDialogue,
+    menu: MessageId,
     msg: Message,
     pool: SqlitePool,
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot, msg, dialogue).await?;
-                return Ok(());
-            }
-            let events_interval = text.parse::<u32>().unwrap();
+            let events_interval = match parse_interval(text) {
+                Ok(events_interval) => events_interval,
+                Err(error) => {
+                    bot.send_message(msg.chat.id, format!("{error}\n{INTERVAL_HINT}"))
+                        .await?;
+                    return Ok(());
+                }
+            };
+            let confirmation = format!("Интервал изменён: {events_interval}");
             update_user(
                 &pool,
                 UserFilter {
                     id: None,
                     tg_id: None,
                     city: None,
-                    tags: None,
+                    categories: None,
                     notification_time: None,
                     events_interval: Some(events_interval),
                 },
-                msg.chat.id.0 as u64 
+                msg.chat.id.0 as u64,
             )
             .await
             .unwrap();
             dialogue.exit().await?;
+            refresh_settings_menu(&bot, &pool, msg.chat.id, menu).await;
+            confirm_change(&bot, msg.chat.id, confirmation).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "Отправьте ваш город.")
@@ -430,18 +1258,110 @@ async fn receive_edit_events_interval(
     Ok(())
 }
 
-async fn receive_city(bot: Bot, dialogue: MyDialogue, msg: Message) -> HandlerResult {
-    match msg.text() {
-        Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot.clone(), msg.clone(), dialogue.clone()).await?;
+async fn receive_edit_max_price(
+    bot: Bot,
+    dialogue: MyDialogue,
+    menu: MessageId,
+    msg: Message,
+    pool: SqlitePool,
+) -> HandlerResult {
+    let max_price = match parse_price(msg.text().unwrap_or_default()) {
+        Ok(max_price) => max_price,
+        Err(error) => {
+            bot.send_message(msg.chat.id, format!("{error}\n{PRICE_HINT}"))
+                .await?;
+            return Ok(());
+        }
+    };
+    set_max_price(&pool, msg.chat.id.0 as u64, max_price).await?;
+    dialogue.exit().await?;
+    refresh_settings_menu(&bot, &pool, msg.chat.id, menu).await;
+    let Some(user) = get_user(&pool, msg.chat.id.0 as u64).await else {
+        return Ok(());
+    };
+    confirm_change(
+        &bot,
+        msg.chat.id,
+        format!("Цена изменена: {}", price_limit(&user)),
+    )
+    .await?;
+    Ok(())
+}
+
+/// `/edit price <сумма>`, `/edit age <возраст>`: changes a setting without opening the
+/// settings menu.
+async fn cmd_edit(bot: Bot, msg: Message, args: String, pool: SqlitePool) -> HandlerResult {
+    const USAGE: &str = "Использование: /edit price 1500, /edit price любая или /edit age 6+.";
+    let tg_id = msg.chat.id.0 as u64;
+    let Some(mut user) = get_user(&pool, tg_id).await else {
+        bot.send_message(msg.chat.id, "Сначала настройте бота командой /start.")
+            .await?;
+        return Ok(());
+    };
+    let (setting, value) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    let confirmation = match setting.to_lowercase().as_str() {
+        "price" | "цена" => match parse_price(value) {
+            Ok(max_price) => {
+                set_max_price(&pool, tg_id, max_price).await?;
+                user.max_price = max_price;
+                format!("Цена изменена: {}", price_limit(&user))
+            }
+            Err(error) => {
+                bot.send_message(msg.chat.id, format!("{error}\n{USAGE}"))
+                    .await?;
                 return Ok(());
             }
-            bot.send_message(msg.chat.id, format!("Выберите категории событий."))
-                .await?;
-            dialogue
-                .update(State::Categories { city: text.into() })
-                .await?;
+        },
+        "age" | "возраст" => match parse_age_filter(value) {
+            Ok(age_filter) => {
+                set_age_filter(&pool, tg_id, age_filter).await?;
+                format!("Возраст изменён: {age_filter}")
+            }
+            Err(error) => {
+                bot.send_message(msg.chat.id, format!("{error}\n{AGE_HINT}"))
+                    .await?;
+                return Ok(());
+            }
+        },
+        _ => {
+            bot.send_message(msg.chat.id, USAGE).await?;
+            return Ok(());
+        }
+    };
+    confirm_change(&bot, msg.chat.id, confirmation).await?;
+    Ok(())
+}
+
+/// Unknown cities are kept as typed, so a stale directory never blocks onboarding.
+fn resolve_city(directory: &SharedDirectory, text: &str) -> String {
+    directory
+        .read()
+        .unwrap()
+        .city_id(text)
+        .unwrap_or_else(|| text.trim().to_string())
+}
+
+async fn receive_city(
+    bot: Bot,
+    dialogue: MyDialogue,
+    msg: Message,
+    pool: SqlitePool,
+    config: Arc<Config>,
+    directory: SharedDirectory,
+) -> HandlerResult {
+    match msg.text() {
+        Some(text) => {
+            let city = resolve_city(&directory, text);
+            let categories = directory::categories(&pool, &config, &city).await;
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "Выберите категории событий через запятую: {}",
+                    category::rubric_names(&categories)
+                ),
+            )
+            .await?;
+            dialogue.update(State::Categories { city }).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "Отправьте ваш город.")
@@ -456,39 +1376,27 @@ async fn receive_categories(
     dialogue: MyDialogue,
     city: String,
     msg: Message,
+    pool: SqlitePool,
+    config: Arc<Config>,
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot.clone(), msg.clone(), dialogue.clone()).await?;
-                return Ok(());
-            }
-            let parts = text.split(',');
-            let mut categories = Vec::new();
-            for part in parts {
-                match CATEGORIES.iter().find(|el| **el == part.trim()) {
-                    Some(_text) => {}
-                    None => {
-                        bot.send_message(msg.chat.id, format!("{part} не категория."))
-                            .await?;
-                        dialogue
-                            .update(State::Categories { city: city.clone() })
-                            .await?;
-                        return Ok(());
-                    }
-                };
-                categories.push(part.to_string());
-            }
+            let known = directory::categories(&pool, &config, &city).await;
+            let categories = match parse_categories(text, &known) {
+                Ok(categories) => categories,
+                Err(error) => {
+                    bot.send_message(msg.chat.id, error.to_string()).await?;
+                    return Ok(());
+                }
+            };
             bot.send_message(
                 msg.chat.id,
-                format!("Выберите время оповещения. Пример: 22:10:57"),
+                format!("Выберите время оповещения или введите его сообщением. {TIME_HINT}"),
             )
+            .reply_markup(hour_keyboard())
             .await?;
             dialogue
-                .update(State::NotificationTime {
-                    city: city,
-                    categories: categories,
-                })
+                .update(State::NotificationTime { city, categories })
                 .await?;
         }
         None => {
@@ -503,49 +1411,20 @@ async fn receive_categories(
 async fn receive_notification_time(
     bot: Bot,
     dialogue: MyDialogue,
-    (city, categories): (String, Vec<String>),
+    (city, categories): (String, Vec<Category>),
     msg: Message,
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot.clone(), msg.clone(), dialogue.clone()).await?;
-                return Ok(());
-            }
-            bot.send_message(
-                msg.chat.id,
-                format!("Выберите интервал для предстоящих событий в днях."),
-            )
-            .await?;
-            //input example 22:10
-            let parts: Vec<&str> = text.split(':').collect();
-            let hour = match parts[0].parse::<u32>() {
-                Ok(num) => num,
-                Err(_) => {
-                    dialogue.update(State::EditNotificationTime).await?;
-                    return Ok(())
-                },
-            };
-            let min = match parts[1].parse::<u32>() {
-                Ok(num) => num,
-                Err(_) => {
-                    dialogue.update(State::EditNotificationTime).await?;
-                    return Ok(())
-                },
+            let naive_time = match parse_time(text) {
+                Ok(naive_time) => naive_time,
+                Err(error) => {
+                    bot.send_message(msg.chat.id, format!("{error} {TIME_HINT}"))
+                        .await?;
+                    return Ok(());
+                }
             };
-            let naive_time = NaiveTime::from_hms_opt(
-                hour,
-                min,
-                0,
-            )
-            .unwrap();
-            dialogue
-                .update(State::EventsInterval {
-                    city,
-                    categories,
-                    notification_time: naive_time,
-                })
-                .await?;
+            ask_events_interval(&bot, &dialogue, msg.chat.id, city, categories, naive_time).await?;
         }
         None => {
             bot.send_message(msg.chat.id, "Выберите интервал.").await?;
@@ -555,22 +1434,120 @@ async fn receive_notification_time(
     Ok(())
 }
 
+async fn ask_events_interval(
+    bot: &Bot,
+    dialogue: &MyDialogue,
+    chat_id: ChatId,
+    city: String,
+    categories: Vec<Category>,
+    notification_time: NaiveTime,
+) -> HandlerResult {
+    bot.send_message(
+        chat_id,
+        format!("Выберите интервал для предстоящих событий.\n{INTERVAL_HINT}"),
+    )
+    .await?;
+    dialogue
+        .update(State::EventsInterval {
+            city,
+            categories,
+            notification_time,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Handles picker navigation in place and returns the time once a minute button is pressed.
+async fn receive_time_picker(
+    bot: &Bot,
+    q: CallbackQuery,
+) -> Result<Option<(ChatId, NaiveTime)>, RequestError> {
+    bot.answer_callback_query(q.id).await?;
+    let (Some(message), Some(button)) = (q.message, q.data.as_deref().and_then(parse_time_button))
+    else {
+        return Ok(None);
+    };
+    match button {
+        TimeButton::Hour(hour) => {
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                format!("Выберите минуты для {hour:02} ч."),
+            )
+            .reply_markup(minute_keyboard(hour))
+            .await?;
+        }
+        TimeButton::Hours => {
+            bot.edit_message_text(message.chat.id, message.id, "Выберите час.")
+                .reply_markup(hour_keyboard())
+                .await?;
+        }
+        TimeButton::Manual => {
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                format!("Введите время сообщением. {TIME_HINT}"),
+            )
+            .await?;
+        }
+        TimeButton::Time(time) => {
+            bot.edit_message_text(
+                message.chat.id,
+                message.id,
+                format!("Время оповещения: {}", time.format("%H:%M")),
+            )
+            .await?;
+            return Ok(Some((message.chat.id, time)));
+        }
+    }
+    Ok(None)
+}
+
+async fn receive_time_button(
+    bot: Bot,
+    dialogue: MyDialogue,
+    (city, categories): (String, Vec<Category>),
+    q: CallbackQuery,
+) -> HandlerResult {
+    if let Some((chat_id, time)) = receive_time_picker(&bot, q).await? {
+        ask_events_interval(&bot, &dialogue, chat_id, city, categories, time).await?;
+    }
+    Ok(())
+}
+
+async fn receive_edit_time_button(
+    bot: Bot,
+    dialogue: MyDialogue,
+    menu: MessageId,
+    q: CallbackQuery,
+    pool: SqlitePool,
+) -> HandlerResult {
+    if let Some((chat_id, time)) = receive_time_picker(&bot, q).await? {
+        save_notification_time(&bot, &dialogue, &pool, chat_id, menu, time).await?;
+    }
+    Ok(())
+}
+
 async fn receive_events_interval(
     bot: Bot,
     dialogue: MyDialogue,
-    (city, categories, notification_time): (String, Vec<String>, NaiveTime),
+    (city, categories, notification_time): (String, Vec<Category>, NaiveTime),
     msg: Message,
     pool: SqlitePool,
 ) -> HandlerResult {
     match msg.text() {
         Some(text) => {
-            if text == "/cancel" {
-                cmd_cancel(bot.clone(), msg.clone(), dialogue.clone()).await?;
-                return Ok(());
-            }
             let tg_id = msg.from().unwrap().id.0;
-            let categories_to_print = categories.join(" ");
-            let events_interval = text.parse::<u32>().unwrap();
+            let rubrics = directory::cached_categories(&pool, &city).await;
+            let categories_to_print = category::names(&categories, &rubrics);
+            let events_interval = match parse_interval(text) {
+                Ok(events_interval) => events_interval,
+                Err(error) => {
+                    bot.send_message(msg.chat.id, format!("{error}\n{INTERVAL_HINT}"))
+                        .await?;
+                    return Ok(());
+                }
+            };
             bot.send_message(
                 msg.chat.id,
                 format!(
@@ -582,11 +1559,21 @@ async fn receive_events_interval(
 
             let user = User {
                 id: -1,
-                tg_id: tg_id,
-                city: city,
-                tags: categories,
-                notification_time: notification_time,
-                events_interval: events_interval,
+                tg_id,
+                city,
+                categories,
+                notification_time,
+                events_interval,
+                active: true,
+                paused: false,
+                blocked_at: None,
+                paused_until: None,
+                last_digest_at: None,
+                language: msg.from().and_then(|from| from.language_code.clone()),
+                next_fire_at: None,
+                max_price: None,
+                free_only: false,
+                age_filter: AgeFilter::Any,
             };
             insert_user(&pool, user.clone()).await;
         }
@@ -597,3 +1584,363 @@ async fn receive_events_interval(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use afisha_bot::db::upsert_user;
+
+    use super::*;
+
+    #[test]
+    fn cancel_is_a_command() {
+        assert!(matches!(
+            Command::parse("/cancel", "afisha_bot"),
+            Ok(Command::Cancel)
+        ));
+    }
+
+    #[test]
+    fn cancel_outside_dialogue() {
+        assert_eq!(cancel_reply(&State::Start, true), "Нечего отменять.");
+        assert_eq!(cancel_reply(&State::Start, false), "Нечего отменять.");
+    }
+
+    #[test]
+    fn cancel_during_onboarding() {
+        let states = [
+            State::City,
+            State::Categories {
+                city: "moscow".into(),
+            },
+            State::NotificationTime {
+                city: "moscow".into(),
+                categories: vec![Category::Concert],
+            },
+            State::EventsInterval {
+                city: "moscow".into(),
+                categories: vec![Category::Concert],
+                notification_time: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            },
+        ];
+        for state in &states {
+            assert_eq!(
+                cancel_reply(state, false),
+                "Настройка отменена. Чтобы начать заново, отправьте /start."
+            );
+            assert_eq!(
+                cancel_reply(state, true),
+                "Настройка отменена, прежние параметры сохранены."
+            );
+        }
+    }
+
+    #[test]
+    fn cancel_while_editing() {
+        let menu = MessageId(1);
+        let states = [
+            State::EditCity { menu },
+            State::EditCategories { menu },
+            State::EditNotificationTime { menu },
+            State::EditEventsInterval { menu },
+            State::EditMaxPrice { menu },
+        ];
+        for state in &states {
+            assert_eq!(
+                cancel_reply(state, true),
+                "Изменение отменено, прежние параметры сохранены."
+            );
+        }
+    }
+
+    #[test]
+    fn cancel_broadcast() {
+        let state = State::ConfirmBroadcast {
+            text: "hi".into(),
+            filter: BroadcastFilter::default(),
+        };
+        assert_eq!(cancel_reply(&state, true), "Рассылка отменена.");
+    }
+
+    const CHAT_ID: i64 = 42;
+
+    type Requests = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    /// Stands in for the Bot API: records every request and answers it the way
+    /// Telegram would.
+    async fn mock_telegram() -> (Bot, Requests) {
+        mock_telegram_with(None).await
+    }
+
+    /// Like [`mock_telegram`], but every message edit fails with `edit_error`.
+    async fn mock_telegram_with(edit_error: Option<ApiError>) -> (Bot, Requests) {
+        use axum::{
+            body::Bytes,
+            extract::{Path, State},
+            routing::post,
+            Json, Router,
+        };
+
+        async fn answer(
+            State((requests, edit_error)): State<(Requests, Option<ApiError>)>,
+            Path((_, method)): Path<(String, String)>,
+            body: Bytes,
+        ) -> Json<serde_json::Value> {
+            let body = serde_json::from_slice(&body).unwrap_or_default();
+            requests.lock().unwrap().push((method.clone(), body));
+            if let Some(error) = edit_error.filter(|_| method.starts_with("EditMessage")) {
+                return Json(serde_json::json!({
+                    "ok": false,
+                    "error_code": 400,
+                    "description": error.to_string(),
+                }));
+            }
+            let result = match method.as_str() {
+                "SendMessage" | "EditMessageText" | "EditMessageReplyMarkup" => message_json("ok"),
+                _ => serde_json::json!(true),
+            };
+            Json(serde_json::json!({ "ok": true, "result": result }))
+        }
+
+        let requests = Requests::default();
+        let app = Router::new()
+            .route("/:token/:method", post(answer))
+            .with_state((requests.clone(), edit_error));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        let bot = Bot::new("1:test").set_api_url(format!("http://{address}").parse().unwrap());
+        (bot, requests)
+    }
+
+    fn message_json(text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": CHAT_ID, "type": "private", "first_name": "Test" },
+            "from": { "id": CHAT_ID, "is_bot": false, "first_name": "Test" },
+            "text": text,
+        })
+    }
+
+    fn text(text: &str) -> Update {
+        update(serde_json::json!({ "update_id": 1, "message": message_json(text) }))
+    }
+
+    fn command(text: &str) -> Update {
+        let mut message = message_json(text);
+        message["entities"] = serde_json::json!([
+            { "type": "bot_command", "offset": 0, "length": text.len() }
+        ]);
+        update(serde_json::json!({ "update_id": 1, "message": message }))
+    }
+
+    fn button(data: &str) -> Update {
+        update(serde_json::json!({
+            "update_id": 1,
+            "callback_query": {
+                "id": "query",
+                "from": { "id": CHAT_ID, "is_bot": false, "first_name": "Test" },
+                "chat_instance": "test",
+                "message": message_json("picker"),
+                "data": data,
+            },
+        }))
+    }
+
+    // Update's deserializer can't read from a `serde_json::Value`.
+    fn update(json: serde_json::Value) -> Update {
+        serde_json::from_str(&json.to_string()).unwrap()
+    }
+
+    async fn test_pool() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_db(&pool).await;
+        pool
+    }
+
+    /// Runs `update` through [`schema`] with the chat in `state`; returns the state
+    /// the dialogue is left in.
+    async fn dispatch(bot: Bot, state: State, update: Update) -> Option<State> {
+        dispatch_with(bot, test_pool().await, state, update).await
+    }
+
+    async fn dispatch_with(
+        bot: Bot,
+        pool: SqlitePool,
+        state: State,
+        update: Update,
+    ) -> Option<State> {
+        use teloxide::dispatching::dialogue::Storage;
+
+        let storage = InMemStorage::<State>::new();
+        storage
+            .clone()
+            .update_dialogue(ChatId(CHAT_ID), state)
+            .await
+            .unwrap();
+        let me: teloxide::types::Me = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "is_bot": true,
+            "first_name": "Afisha",
+            "username": "afisha_bot",
+            "can_join_groups": false,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        }))
+        .unwrap();
+        let directory: SharedDirectory = Arc::new(RwLock::new(Directory { cities: Vec::new() }));
+        let result = schema()
+            .dispatch(dptree::deps![
+                update,
+                bot,
+                me,
+                storage.clone(),
+                pool,
+                directory,
+                Arc::new(Config::default())
+            ])
+            .await;
+        assert!(
+            matches!(result, std::ops::ControlFlow::Break(Ok(()))),
+            "update was not handled"
+        );
+        storage.get_dialogue(ChatId(CHAT_ID)).await.unwrap()
+    }
+
+    fn all_states() -> Vec<State> {
+        let menu = MessageId(1);
+        vec![
+            State::Start,
+            State::City,
+            State::Categories {
+                city: "moscow".into(),
+            },
+            State::NotificationTime {
+                city: "moscow".into(),
+                categories: vec![Category::Concert],
+            },
+            State::EventsInterval {
+                city: "moscow".into(),
+                categories: vec![Category::Concert],
+                notification_time: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            },
+            State::EditCity { menu },
+            State::EditCategories { menu },
+            State::EditNotificationTime { menu },
+            State::EditEventsInterval { menu },
+            State::EditMaxPrice { menu },
+            State::ConfirmBroadcast {
+                text: "hi".into(),
+                filter: BroadcastFilter::default(),
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn cancel_leaves_every_state() {
+        for state in all_states() {
+            let (bot, requests) = mock_telegram().await;
+            let name = state.name();
+            let reply = cancel_reply(&state, false);
+
+            let state = dispatch(bot, state, command("/cancel")).await;
+
+            assert!(state.is_none(), "{name}: dialogue is still open");
+            let requests = requests.lock().unwrap();
+            let [(method, body)] = requests.as_slice() else {
+                panic!("{name}: expected one request, got {requests:?}");
+            };
+            assert_eq!(method, "SendMessage", "{name}");
+            assert_eq!(body["text"], reply, "{name}");
+            assert_eq!(body["reply_markup"]["remove_keyboard"], true, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_button_is_answered() {
+        let buttons = ["time:hour:9", "broadcast:confirm", "unknown"];
+        for data in buttons {
+            let (bot, requests) = mock_telegram().await;
+
+            dispatch(bot, State::Start, button(data)).await;
+
+            let requests = requests.lock().unwrap();
+            let [(method, body)] = requests.as_slice() else {
+                panic!("{data}: expected one request, got {requests:?}");
+            };
+            assert_eq!(method, "AnswerCallbackQuery", "{data}");
+            assert_eq!(body["text"], "Это меню устарело.", "{data}");
+        }
+    }
+
+    #[tokio::test]
+    async fn preview_of_stopped_digest() {
+        let (bot, requests) = mock_telegram().await;
+        let pool = test_pool().await;
+        let user = User {
+            tg_id: CHAT_ID as u64,
+            city: "msk".into(),
+            active: false,
+            ..User::default()
+        };
+        upsert_user(&pool, &user).await.unwrap();
+
+        dispatch_with(bot, pool, State::Start, command("/preview")).await;
+
+        let requests = requests.lock().unwrap();
+        let [(method, body)] = requests.as_slice() else {
+            panic!("expected one request, got {requests:?}");
+        };
+        assert_eq!(method, "SendMessage");
+        assert_eq!(
+            body["text"],
+            "Следующий дайджест не запланирован: рассылка остановлена или на паузе."
+        );
+    }
+
+    #[tokio::test]
+    async fn change_is_confirmed_when_menu_edit_fails() {
+        // Not modified: the new price equals the old one. Not found: the menu is gone.
+        for edit_error in [
+            ApiError::MessageNotModified,
+            ApiError::MessageToEditNotFound,
+        ] {
+            let (bot, requests) = mock_telegram_with(Some(edit_error.clone())).await;
+            let pool = test_pool().await;
+            let user = User {
+                tg_id: CHAT_ID as u64,
+                city: "msk".into(),
+                max_price: Some(1000),
+                ..User::default()
+            };
+            upsert_user(&pool, &user).await.unwrap();
+            let state = State::EditMaxPrice { menu: MessageId(7) };
+
+            let state = dispatch_with(bot, pool, state, text("1000")).await;
+
+            assert!(state.is_none(), "{edit_error:?}");
+            let requests = requests.lock().unwrap();
+            let methods: Vec<&str> = requests.iter().map(|(method, _)| method.as_str()).collect();
+            assert_eq!(
+                methods,
+                ["EditMessageText", "SendMessage"],
+                "{edit_error:?}"
+            );
+            let confirmation = &requests[1].1;
+            assert!(confirmation["text"]
+                .as_str()
+                .unwrap()
+                .starts_with("Цена изменена"));
+            assert!(confirmation["reply_markup"]["inline_keyboard"].is_array());
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+/// Process-wide Prometheus metrics and the timestamps `/readyz` checks.
+pub struct Metrics {
+    registry: Registry,
+    pub digests_sent: IntCounter,
+    pub digests_failed: IntCounter,
+    pub afisha_requests: HistogramVec,
+    pub afisha_errors: IntCounterVec,
+    pub db_queries: Histogram,
+    pub active_users: IntGauge,
+    /// Event cache lookups by `result`: `hit` or `miss`.
+    pub cache: IntCounterVec,
+    /// Updates handled by the dialogue `state` they arrived in.
+    pub dialogue_states: IntCounterVec,
+    last_tick: AtomicI64,
+    last_afisha_success: AtomicI64,
+    afisha_up: AtomicBool,
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("afisha".into()), None).unwrap();
+        let metrics = Self {
+            digests_sent: IntCounter::new("digests_sent_total", "Digest messages delivered")
+                .unwrap(),
+            digests_failed: IntCounter::new(
+                "digests_failed_total",
+                "Digests that could not be delivered",
+            )
+            .unwrap(),
+            afisha_requests: HistogramVec::new(
+                HistogramOpts::new("api_request_duration_seconds", "Afisha API request latency"),
+                &["endpoint"],
+            )
+            .unwrap(),
+            afisha_errors: IntCounterVec::new(
+                Opts::new("api_errors_total", "Failed Afisha API requests"),
+                &["endpoint"],
+            )
+            .unwrap(),
+            db_queries: Histogram::with_opts(HistogramOpts::new(
+                "db_query_duration_seconds",
+                "Latency of scheduler and health check queries",
+            ))
+            .unwrap(),
+            active_users: IntGauge::new("active_users", "Users receiving digests").unwrap(),
+            cache: IntCounterVec::new(
+                Opts::new("cache_requests_total", "Event cache lookups"),
+                &["result"],
+            )
+            .unwrap(),
+            dialogue_states: IntCounterVec::new(
+                Opts::new("dialogue_updates_total", "Updates by dialogue state"),
+                &["state"],
+            )
+            .unwrap(),
+            last_tick: AtomicI64::new(0),
+            last_afisha_success: AtomicI64::new(0),
+            afisha_up: AtomicBool::new(false),
+            registry,
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 8] = [
+            Box::new(metrics.digests_sent.clone()),
+            Box::new(metrics.digests_failed.clone()),
+            Box::new(metrics.afisha_requests.clone()),
+            Box::new(metrics.afisha_errors.clone()),
+            Box::new(metrics.db_queries.clone()),
+            Box::new(metrics.active_users.clone()),
+            Box::new(metrics.cache.clone()),
+            Box::new(metrics.dialogue_states.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// Metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// Times one Afisha request and remembers whether the API was reachable. Client
+    /// errors such as an unknown city come from what users typed, so they are counted
+    /// but don't mark Afisha as down.
+    pub fn observe_afisha<T>(
+        &self,
+        endpoint: &str,
+        started: Instant,
+        result: &Result<T, reqwest::Error>,
+    ) {
+        self.afisha_requests
+            .with_label_values(&[endpoint])
+            .observe(started.elapsed().as_secs_f64());
+        match result {
+            Ok(_) => {
+                store_now(&self.last_afisha_success);
+                self.afisha_up.store(true, Ordering::Relaxed);
+            }
+            Err(error) => {
+                self.afisha_errors.with_label_values(&[endpoint]).inc();
+                if is_outage(error) {
+                    self.afisha_up.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    pub fn tick(&self) {
+        store_now(&self.last_tick);
+    }
+
+    pub fn last_tick(&self) -> Option<DateTime<Utc>> {
+        load(&self.last_tick)
+    }
+
+    /// Whether Afisha answered since it was last unreachable.
+    pub fn afisha_ok(&self) -> bool {
+        self.afisha_up.load(Ordering::Relaxed)
+    }
+
+    pub fn last_afisha_success(&self) -> Option<DateTime<Utc>> {
+        load(&self.last_afisha_success)
+    }
+}
+
+/// Whether `error` means Afisha is unavailable rather than that the request was bad:
+/// no usable response, a server error, or rate limiting.
+fn is_outage(error: &reqwest::Error) -> bool {
+    error.status().is_none_or(|status| {
+        status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    })
+}
+
+fn store_now(timestamp: &AtomicI64) {
+    timestamp.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+}
+
+fn load(timestamp: &AtomicI64) -> Option<DateTime<Utc>> {
+    match timestamp.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Utc.timestamp_millis_opt(millis).single(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Path, http::StatusCode, routing::get, Router};
+
+    use super::*;
+
+    /// Stands in for Afisha, answering `/<status>` with that status.
+    fn afisha() -> String {
+        let app = Router::new().route(
+            "/:status",
+            get(|Path(status): Path<u16>| async move { StatusCode::from_u16(status).unwrap() }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+        format!("http://{address}")
+    }
+
+    async fn request(metrics: &Metrics, url: &str) {
+        let started = Instant::now();
+        let result = async { reqwest::get(url).await?.error_for_status() }.await;
+        metrics.observe_afisha("events", started, &result);
+    }
+
+    #[tokio::test]
+    async fn client_errors_keep_afisha_up() {
+        let root = afisha();
+        let metrics = Metrics::new();
+        assert!(!metrics.afisha_ok());
+
+        let steps = [
+            ("200", true),
+            ("404", true),
+            ("400", true),
+            ("503", false),
+            ("200", true),
+            ("429", false),
+        ];
+        for (status, up) in steps {
+            request(&metrics, &format!("{root}/{status}")).await;
+            assert_eq!(metrics.afisha_ok(), up, "after {status}");
+        }
+
+        request(&metrics, &format!("{root}/200")).await;
+        // Nothing listens on the discard port.
+        request(&metrics, "http://127.0.0.1:9/").await;
+        assert!(!metrics.afisha_ok());
+    }
+}
@@ -0,0 +1,183 @@
+use chrono::{NaiveDate, Utc};
+use sqlx::SqlitePool;
+use teloxide::{prelude::*, types::InlineKeyboardMarkup, RequestError};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    db::{
+        deactivate_user, enqueue_messages, fail_chat_messages, fail_message, get_due_messages,
+        mark_message_sent, prune_outbox, retry_message, OutboxMessage,
+    },
+    is_chat_unreachable,
+    metrics::metrics,
+    rate_limit::RateLimiter,
+};
+
+const BATCH_SIZE: i64 = 50;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_ATTEMPTS: i64 = 8;
+const FIRST_RETRY_SECS: i64 = 5;
+const MAX_RETRY_SECS: i64 = 60 * 60;
+const KEEP_DAYS: i64 = 7;
+const DIGEST_PREFIX: &str = "digest:";
+
+/// Same user, date and chunk give the same key, so a digest that is rendered again
+/// after a crash or restart is not delivered twice.
+pub fn digest_key(tg_id: u64, date: NaiveDate, chunk: usize) -> String {
+    format!("{DIGEST_PREFIX}{tg_id}:{date}:{chunk}")
+}
+
+/// Queues the digest `tg_id` gets on `date`; returns how many messages were new.
+pub async fn enqueue_digest(
+    pool: &SqlitePool,
+    tg_id: u64,
+    date: NaiveDate,
+    messages: Vec<String>,
+    keyboard: &InlineKeyboardMarkup,
+) -> Result<u64, sqlx::Error> {
+    let reply_markup = serde_json::to_string(keyboard).unwrap();
+    let messages: Vec<OutboxMessage> = messages
+        .into_iter()
+        .enumerate()
+        .map(|(chunk, text)| OutboxMessage {
+            id: 0,
+            idempotency_key: digest_key(tg_id, date, chunk),
+            chat_id: tg_id as i64,
+            text,
+            reply_markup: Some(reply_markup.clone()),
+            attempts: 0,
+        })
+        .collect();
+    enqueue_messages(pool, &messages).await
+}
+
+/// Delivers queued messages until `shutdown`, within the send budget of `limiter`.
+/// Failed sends are retried with exponential backoff; messages to chats that blocked
+/// the bot are dropped.
+pub async fn run_worker(
+    bot: Bot,
+    pool: SqlitePool,
+    limiter: RateLimiter,
+    shutdown: CancellationToken,
+) {
+    let mut pruned_at: Option<Instant> = None;
+    while !shutdown.is_cancelled() {
+        if pruned_at.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+            let before = Utc::now() - chrono::Duration::days(KEEP_DAYS);
+            if let Err(error) = prune_outbox(&pool, before).await {
+                log::error!("Failed to prune outbox: {error}");
+            }
+            pruned_at = Some(Instant::now());
+        }
+
+        let messages = match get_due_messages(&pool, Utc::now(), BATCH_SIZE).await {
+            Ok(messages) => messages,
+            Err(error) => {
+                log::error!("Failed to load outbox: {error}");
+                Vec::new()
+            }
+        };
+        if messages.is_empty() {
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = sleep(POLL_INTERVAL) => {}
+            }
+            continue;
+        }
+        for message in messages {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = limiter.acquire() => {}
+            }
+            if let Err(error) = deliver(&bot, &pool, &message).await {
+                log::error!("Failed to update outbox message {}: {error}", message.id);
+            }
+        }
+    }
+}
+
+async fn deliver(bot: &Bot, pool: &SqlitePool, message: &OutboxMessage) -> Result<(), sqlx::Error> {
+    let is_digest = message.idempotency_key.starts_with(DIGEST_PREFIX);
+    let mut request = bot.send_message(ChatId(message.chat_id), &message.text);
+    if let Some(markup) = &message.reply_markup {
+        request =
+            request.reply_markup(serde_json::from_str::<InlineKeyboardMarkup>(markup).unwrap());
+    }
+    match request.await {
+        Ok(_) => {
+            mark_message_sent(pool, message.id).await?;
+            if is_digest {
+                metrics().digests_sent.inc();
+            }
+        }
+        Err(RequestError::RetryAfter(duration)) => {
+            let next_attempt_at = Utc::now() + chrono::Duration::from_std(duration).unwrap();
+            retry_message(
+                pool,
+                message.id,
+                message.attempts,
+                next_attempt_at,
+                "retry after",
+            )
+            .await?;
+        }
+        Err(error) if is_chat_unreachable(&error) => {
+            log::info!("Deactivating chat {}: {error}", message.chat_id);
+            fail_chat_messages(pool, message.chat_id, &error.to_string()).await?;
+            deactivate_user(pool, message.chat_id as u64, Utc::now()).await?;
+            if is_digest {
+                metrics().digests_failed.inc();
+            }
+        }
+        Err(error) => {
+            let attempts = message.attempts + 1;
+            if attempts >= MAX_ATTEMPTS {
+                log::warn!(
+                    "Giving up on {} after {attempts} attempts: {error}",
+                    message.idempotency_key
+                );
+                fail_message(pool, message.id, &error.to_string()).await?;
+                if is_digest {
+                    metrics().digests_failed.inc();
+                }
+            } else {
+                let next_attempt_at = Utc::now() + backoff(attempts);
+                retry_message(
+                    pool,
+                    message.id,
+                    attempts,
+                    next_attempt_at,
+                    &error.to_string(),
+                )
+                .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 5s, 10s, 20s, ... up to an hour.
+fn backoff(attempts: i64) -> chrono::Duration {
+    let secs = FIRST_RETRY_SECS.saturating_mul(1 << (attempts - 1).clamp(0, 20));
+    chrono::Duration::seconds(secs.min(MAX_RETRY_SECS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_an_hour() {
+        let delays: Vec<i64> = (1..=12)
+            .map(|attempts| backoff(attempts).num_seconds())
+            .collect();
+        assert_eq!(
+            delays,
+            [5, 10, 20, 40, 80, 160, 320, 640, 1280, 2560, 3600, 3600]
+        );
+        assert_eq!(backoff(i64::MAX).num_seconds(), MAX_RETRY_SECS);
+        assert_eq!(backoff(0).num_seconds(), FIRST_RETRY_SECS);
+    }
+}
@@ -0,0 +1,708 @@
+use std::fmt;
+
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+
+use crate::{
+    api::{AgeFilter, EventsInterval},
+    category::{Category, Rubric, UnknownCategory},
+};
+
+pub const TIME_HINT: &str = "Например: 9, 9:30, 09.30, 9 утра или 21ч.";
+pub const PRICE_HINT: &str = "Например: 1500, 2 000 ₽ или «любая», чтобы снять ограничение.";
+pub const AGE_HINT: &str =
+    "Варианты: 0+, 6+, 12+, 16+, 18+, «семья», «без детских» или «любой», чтобы снять ограничение.";
+pub const SNOOZE_HINT: &str = "Например: 3d, 2w, 12h, неделя, понедельник или дата: 2026-11-01.";
+pub const INTERVAL_HINT: &str =
+    "Варианты: сегодня, завтра, выходные, неделя, месяц.\nИли диапазон дат: 01.11.2026-05.11.2026";
+
+#[derive(Debug, PartialEq)]
+pub enum TimeError {
+    Empty,
+    Format,
+    Hour(u32),
+    Minute(u32),
+}
+
+impl fmt::Display for TimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeError::Empty => write!(f, "Отправьте время оповещения."),
+            TimeError::Format => write!(f, "Не понял время."),
+            TimeError::Hour(hour) => write!(f, "В сутках нет часа {hour}."),
+            TimeError::Minute(minute) => write!(f, "В часе нет минуты {minute}."),
+        }
+    }
+}
+
+impl std::error::Error for TimeError {}
+
+#[derive(Debug, PartialEq)]
+pub enum IntervalError {
+    Empty,
+    Unknown(String),
+    Date(String),
+    ReversedRange,
+}
+
+impl fmt::Display for IntervalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntervalError::Empty => write!(f, "Отправьте интервал."),
+            IntervalError::Unknown(text) => write!(f, "Не понял интервал «{text}»."),
+            IntervalError::Date(text) => write!(f, "«{text}» не похоже на дату ДД.ММ.ГГГГ."),
+            IntervalError::ReversedRange => write!(f, "Конец диапазона раньше начала."),
+        }
+    }
+}
+
+impl std::error::Error for IntervalError {}
+
+#[derive(Debug, PartialEq)]
+pub enum PriceError {
+    Empty,
+    Format(String),
+}
+
+impl fmt::Display for PriceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceError::Empty => write!(f, "Отправьте максимальную цену."),
+            PriceError::Format(text) => write!(f, "Не понял цену «{text}»."),
+        }
+    }
+}
+
+impl std::error::Error for PriceError {}
+
+#[derive(Debug, PartialEq)]
+pub enum AgeError {
+    Empty,
+    Format(String),
+}
+
+impl fmt::Display for AgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgeError::Empty => write!(f, "Отправьте возрастное ограничение."),
+            AgeError::Format(text) => write!(f, "Не понял возраст «{text}»."),
+        }
+    }
+}
+
+impl std::error::Error for AgeError {}
+
+#[derive(Debug, PartialEq)]
+pub enum SnoozeError {
+    Empty,
+    Format(String),
+    NotPositive,
+    TooLong,
+    Past,
+}
+
+impl fmt::Display for SnoozeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnoozeError::Empty => write!(f, "Укажите срок паузы."),
+            SnoozeError::Format(text) => write!(f, "Не понял срок «{text}»."),
+            SnoozeError::NotPositive => write!(f, "Срок паузы должен быть больше нуля."),
+            SnoozeError::TooLong => write!(f, "Пауза не может быть дольше года."),
+            SnoozeError::Past => write!(f, "Эта дата уже прошла."),
+        }
+    }
+}
+
+impl std::error::Error for SnoozeError {}
+
+#[derive(Debug, PartialEq)]
+pub enum CategoriesError {
+    Empty,
+    Unknown(UnknownCategory),
+}
+
+impl fmt::Display for CategoriesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CategoriesError::Empty => write!(f, "Отправьте категории."),
+            CategoriesError::Unknown(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for CategoriesError {}
+
+#[derive(Clone, Copy)]
+enum DayPart {
+    Morning,
+    Afternoon,
+    Evening,
+    Night,
+}
+
+const DAY_PARTS: [(&str, DayPart); 6] = [
+    ("вечера", DayPart::Evening),
+    ("утра", DayPart::Morning),
+    ("ночи", DayPart::Night),
+    ("дня", DayPart::Afternoon),
+    ("am", DayPart::Morning),
+    ("pm", DayPart::Evening),
+];
+// Longest first so "часов" is stripped whole rather than by its trailing "ч".
+const HOUR_WORDS: [&str; 4] = ["часов", "часа", "час", "ч"];
+
+/// Parses notification time the way people type it: `9`, `9:30`, `09.30`,
+/// `9 утра`, `21ч`, `7 вечера`. Seconds, if given, are ignored.
+pub fn parse_time(text: &str) -> Result<NaiveTime, TimeError> {
+    let mut text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Err(TimeError::Empty);
+    }
+
+    let mut day_part = None;
+    if let Some((word, part)) = DAY_PARTS.iter().find(|(word, _)| text.ends_with(word)) {
+        text.truncate(text.len() - word.len());
+        text = text.trim_end().to_string();
+        day_part = Some(*part);
+    }
+    if let Some(word) = HOUR_WORDS.iter().find(|word| text.ends_with(*word)) {
+        text.truncate(text.len() - word.len());
+        text = text.trim_end().to_string();
+    }
+
+    let mut parts = text.split([':', '.']);
+    let hour = parse_number(parts.next())?;
+    let minute = match parts.next() {
+        Some(minute) => parse_number(Some(minute))?,
+        None => 0,
+    };
+    if let Some(second) = parts.next() {
+        parse_number(Some(second))?;
+    }
+    if parts.next().is_some() {
+        return Err(TimeError::Format);
+    }
+
+    let hour = match (day_part, hour) {
+        (None, hour) => hour,
+        (Some(DayPart::Morning), 12) => 0,
+        (Some(DayPart::Morning), 1..=11) => hour,
+        (Some(DayPart::Afternoon), 12) => 12,
+        (Some(DayPart::Afternoon), 1..=5) => hour + 12,
+        (Some(DayPart::Evening), 1..=11) => hour + 12,
+        (Some(DayPart::Evening), 12..=23) => hour,
+        (Some(DayPart::Night), 12) => 0,
+        (Some(DayPart::Night), 1..=5) => hour,
+        (Some(_), hour) => return Err(TimeError::Hour(hour)),
+    };
+    if hour > 23 {
+        return Err(TimeError::Hour(hour));
+    }
+    if minute > 59 {
+        return Err(TimeError::Minute(minute));
+    }
+    Ok(NaiveTime::from_hms_opt(hour, minute, 0).unwrap())
+}
+
+fn parse_number(part: Option<&str>) -> Result<u32, TimeError> {
+    match part {
+        Some(part) if !part.is_empty() && part.len() <= 2 => {
+            part.parse().map_err(|_| TimeError::Format)
+        }
+        _ => Err(TimeError::Format),
+    }
+}
+
+/// Parses an interval name (`сегодня`, `выходные`, `7 дней`, ...) or an explicit
+/// date range `01.11.2026-05.11.2026`.
+pub fn parse_interval(text: &str) -> Result<EventsInterval, IntervalError> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Err(IntervalError::Empty);
+    }
+    let interval = match text.as_str() {
+        "сегодня" | "today" | "1" => EventsInterval::Today,
+        "завтра" | "tomorrow" => EventsInterval::Tomorrow,
+        "выходные" | "на выходных" | "эти выходные" | "weekend" => {
+            EventsInterval::Weekend
+        }
+        "неделя" | "на неделю" | "7 дней" | "7" | "week" => EventsInterval::Week,
+        "месяц" | "на месяц" | "этот месяц" | "month" => EventsInterval::Month,
+        _ => return parse_range(&text),
+    };
+    Ok(interval)
+}
+
+fn parse_range(text: &str) -> Result<EventsInterval, IntervalError> {
+    let Some((from, to)) = text.split_once(['-', '–', '—']) else {
+        return Err(IntervalError::Unknown(text.to_string()));
+    };
+    let from = parse_date(from.trim())?;
+    let to = parse_date(to.trim())?;
+    if to < from {
+        return Err(IntervalError::ReversedRange);
+    }
+    Ok(EventsInterval::Range { from, to })
+}
+
+fn parse_date(text: &str) -> Result<NaiveDate, IntervalError> {
+    NaiveDate::parse_from_str(text, "%d.%m.%Y").map_err(|_| IntervalError::Date(text.to_string()))
+}
+
+const ANY_PRICE: [&str; 5] = ["любая", "любой", "без ограничений", "нет", "-"];
+// Longest first so "руб." is stripped whole rather than by its trailing ".".
+const CURRENCY_WORDS: [&str; 6] = ["рублей", "руб.", "руб", "р.", "р", "₽"];
+
+/// Parses the most a user wants to pay in rubles: `1500`, `2 000 ₽`, `500р`.
+/// `любая` and the like lift the limit.
+pub fn parse_price(text: &str) -> Result<Option<u32>, PriceError> {
+    let mut text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Err(PriceError::Empty);
+    }
+    if ANY_PRICE.contains(&text.as_str()) {
+        return Ok(None);
+    }
+    if let Some(word) = CURRENCY_WORDS.iter().find(|word| text.ends_with(*word)) {
+        text.truncate(text.len() - word.len());
+    }
+    let digits: String = text.chars().filter(|char| !char.is_whitespace()).collect();
+    if digits.is_empty() || !digits.chars().all(|char| char.is_ascii_digit()) {
+        return Err(PriceError::Format(text.trim().to_string()));
+    }
+    digits
+        .parse()
+        .map(Some)
+        .map_err(|_| PriceError::Format(text.trim().to_string()))
+}
+
+const ANY_AGE: [&str; 4] = ["любой", "любые", "все", "-"];
+const FAMILY_AGE: [&str; 4] = ["семья", "семейный", "семейный режим", "дети"];
+const NO_KIDS_AGE: [&str; 3] = ["без детских", "без детей", "взрослые"];
+
+/// Parses an age filter: a rating such as `6+` or `12`, or one of the words in
+/// [`AGE_HINT`].
+pub fn parse_age_filter(text: &str) -> Result<AgeFilter, AgeError> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Err(AgeError::Empty);
+    }
+    if ANY_AGE.contains(&text.as_str()) {
+        return Ok(AgeFilter::Any);
+    }
+    if FAMILY_AGE.contains(&text.as_str()) {
+        return Ok(AgeFilter::FAMILY);
+    }
+    if NO_KIDS_AGE.contains(&text.as_str()) {
+        return Ok(AgeFilter::NoKids);
+    }
+    text.trim_end_matches('+')
+        .trim()
+        .parse()
+        .ok()
+        .filter(|age| AgeFilter::RATINGS.contains(age))
+        .map(AgeFilter::UpTo)
+        .ok_or(AgeError::Format(text))
+}
+
+const MAX_SNOOZE_DAYS: i64 = 366;
+const WEEKDAYS: [(&str, Weekday); 21] = [
+    ("понедельник", Weekday::Mon),
+    ("пн", Weekday::Mon),
+    ("вторник", Weekday::Tue),
+    ("вт", Weekday::Tue),
+    ("среда", Weekday::Wed),
+    ("среду", Weekday::Wed),
+    ("ср", Weekday::Wed),
+    ("четверг", Weekday::Thu),
+    ("чт", Weekday::Thu),
+    ("пятница", Weekday::Fri),
+    ("пятницу", Weekday::Fri),
+    ("пт", Weekday::Fri),
+    ("суббота", Weekday::Sat),
+    ("субботу", Weekday::Sat),
+    ("сб", Weekday::Sat),
+    ("воскресенье", Weekday::Sun),
+    ("вс", Weekday::Sun),
+    ("monday", Weekday::Mon),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+/// Parses how long to pause digests, relative to `now` in the bot's time zone, and
+/// returns when they resume:
+/// - a duration: `3d`, `2w`, `12h` (also `3д`, `2н`, `12ч`);
+/// - `день`, `неделя` or `месяц`;
+/// - a weekday, `понедельник` or `пн`: the start of its next occurrence after today;
+/// - a date, `2026-11-01` or `01.11.2026`: the start of that day.
+pub fn parse_snooze<Tz: TimeZone>(
+    text: &str,
+    now: DateTime<Tz>,
+) -> Result<DateTime<Utc>, SnoozeError> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Err(SnoozeError::Empty);
+    }
+    let start_of = |date: NaiveDate| {
+        now.timezone()
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .ok_or_else(|| SnoozeError::Format(text.clone()))
+    };
+    let limited = |until: DateTime<Tz>| {
+        if until.clone() - now.clone() > Duration::days(MAX_SNOOZE_DAYS) {
+            return Err(SnoozeError::TooLong);
+        }
+        Ok(until.with_timezone(&Utc))
+    };
+    for format in ["%Y-%m-%d", "%d.%m.%Y"] {
+        if let Ok(date) = NaiveDate::parse_from_str(&text, format) {
+            let until = start_of(date)?;
+            if until <= now {
+                return Err(SnoozeError::Past);
+            }
+            return limited(until);
+        }
+    }
+    if let Some((_, weekday)) = WEEKDAYS.iter().find(|(name, _)| *name == text) {
+        let today = now.date_naive();
+        let days =
+            (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+        let date = today + Duration::days(if days == 0 { 7 } else { days.into() });
+        return limited(start_of(date)?);
+    }
+    let until = match text.as_str() {
+        "день" | "сутки" => Some(now.clone() + Duration::days(1)),
+        "неделя" | "неделю" => Some(now.clone() + Duration::weeks(1)),
+        "месяц" => now.clone().checked_add_months(Months::new(1)),
+        _ => None,
+    };
+    if let Some(until) = until {
+        return Ok(until.with_timezone(&Utc));
+    }
+
+    let format_error = || SnoozeError::Format(text.clone());
+    let unit = text.chars().last().ok_or_else(format_error)?;
+    let amount: i64 = text[..text.len() - unit.len_utf8()]
+        .trim()
+        .parse()
+        .map_err(|_| format_error())?;
+    let unit_hours = match unit {
+        'h' | 'ч' => 1,
+        'd' | 'д' => 24,
+        'w' | 'н' => 24 * 7,
+        _ => return Err(format_error()),
+    };
+    if amount <= 0 {
+        return Err(SnoozeError::NotPositive);
+    }
+    let hours = amount
+        .checked_mul(unit_hours)
+        .filter(|hours| *hours <= MAX_SNOOZE_DAYS * 24)
+        .ok_or(SnoozeError::TooLong)?;
+    Ok((now + Duration::hours(hours)).with_timezone(&Utc))
+}
+
+/// Parses a comma-separated list of category ids or names, accepting only those in
+/// `known`. Repeated categories are listed once.
+pub fn parse_categories(text: &str, known: &[Rubric]) -> Result<Vec<Category>, CategoriesError> {
+    let mut categories = Vec::new();
+    for part in text.split(',').filter(|part| !part.trim().is_empty()) {
+        let Some(rubric) = known.iter().find(|rubric| rubric.matches(part)) else {
+            return Err(CategoriesError::Unknown(UnknownCategory(
+                part.trim().into(),
+            )));
+        };
+        if !categories.contains(&rubric.category) {
+            categories.push(rubric.category.clone());
+        }
+    }
+    if categories.is_empty() {
+        return Err(CategoriesError::Empty);
+    }
+    Ok(categories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn date(day: u32, month: u32, year: i32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_times() {
+        let cases = [
+            ("9", time(9, 0)),
+            ("09", time(9, 0)),
+            ("0", time(0, 0)),
+            ("9:30", time(9, 30)),
+            ("9:5", time(9, 5)),
+            ("09.30", time(9, 30)),
+            ("22:10:57", time(22, 10)),
+            (" 23:59 ", time(23, 59)),
+            ("9 утра", time(9, 0)),
+            ("12 утра", time(0, 0)),
+            ("9:30 утра", time(9, 30)),
+            ("3 дня", time(15, 0)),
+            ("12 дня", time(12, 0)),
+            ("7 вечера", time(19, 0)),
+            ("2 ночи", time(2, 0)),
+            ("12 ночи", time(0, 0)),
+            ("21ч", time(21, 0)),
+            ("21 ч", time(21, 0)),
+            ("8 часов", time(8, 0)),
+            ("8 часов вечера", time(20, 0)),
+            ("7pm", time(19, 0)),
+            ("7 AM", time(7, 0)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_time(input), Ok(expected), "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_times() {
+        let cases = [
+            ("", TimeError::Empty),
+            ("   ", TimeError::Empty),
+            ("утро", TimeError::Format),
+            ("9:", TimeError::Format),
+            (":30", TimeError::Format),
+            ("9:30:00:00", TimeError::Format),
+            ("930", TimeError::Format),
+            ("-1", TimeError::Format),
+            ("25:00", TimeError::Hour(25)),
+            ("24", TimeError::Hour(24)),
+            ("9:60", TimeError::Minute(60)),
+            ("15 утра", TimeError::Hour(15)),
+            ("8 ночи", TimeError::Hour(8)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_time(input), Err(expected), "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn parses_intervals() {
+        let cases = [
+            ("сегодня", EventsInterval::Today),
+            ("Завтра", EventsInterval::Tomorrow),
+            ("выходные", EventsInterval::Weekend),
+            ("на выходных", EventsInterval::Weekend),
+            ("неделя", EventsInterval::Week),
+            ("7 дней", EventsInterval::Week),
+            ("месяц", EventsInterval::Month),
+            (
+                "01.11.2026-05.11.2026",
+                EventsInterval::Range {
+                    from: date(1, 11, 2026),
+                    to: date(5, 11, 2026),
+                },
+            ),
+            (
+                "01.11.2026 – 01.11.2026",
+                EventsInterval::Range {
+                    from: date(1, 11, 2026),
+                    to: date(1, 11, 2026),
+                },
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_interval(input), Ok(expected), "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_intervals() {
+        let cases = [
+            ("", IntervalError::Empty),
+            ("когда-нибудь", IntervalError::Date("когда".into())),
+            ("год", IntervalError::Unknown("год".into())),
+            (
+                "32.11.2026-05.12.2026",
+                IntervalError::Date("32.11.2026".into()),
+            ),
+            ("05.11.2026-01.11.2026", IntervalError::ReversedRange),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_interval(input), Err(expected), "input: {input:?}");
+        }
+    }
+
+    fn rubric(id: &str, name: &str) -> Rubric {
+        Rubric {
+            category: Category::from_id(id),
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn parses_categories() {
+        let known = [
+            rubric("concert", "Концерты"),
+            rubric("kids", "Детям"),
+            rubric("cinema", "Кино"),
+        ];
+        let cases = [
+            ("concert", vec![Category::Concert]),
+            (
+                "Кино, kids",
+                vec![Category::Cinema, Category::Other("kids".into())],
+            ),
+            (" детям ,kids,", vec![Category::Other("kids".into())]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_categories(input, &known),
+                Ok(expected),
+                "input: {input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_categories() {
+        let known = [rubric("concert", "Концерты"), rubric("kids", "Детям")];
+        let unknown = |text: &str| CategoriesError::Unknown(UnknownCategory(text.into()));
+        let cases = [
+            ("", CategoriesError::Empty),
+            (" , ", CategoriesError::Empty),
+            ("concert, опера", unknown("опера")),
+            ("concert, quest", unknown("quest")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_categories(input, &known),
+                Err(expected),
+                "input: {input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_prices() {
+        let cases = [
+            ("1500", Some(1500)),
+            (" 0 ", Some(0)),
+            ("2 000 ₽", Some(2000)),
+            ("2\u{a0}000", Some(2000)),
+            ("500р", Some(500)),
+            ("700 руб.", Some(700)),
+            ("300 рублей", Some(300)),
+            ("Любая", None),
+            ("без ограничений", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_price(input), Ok(expected), "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_prices() {
+        let cases = [
+            ("", PriceError::Empty),
+            ("дёшево", PriceError::Format("дёшево".into())),
+            ("₽", PriceError::Format("".into())),
+            ("-100", PriceError::Format("-100".into())),
+            ("1.5", PriceError::Format("1.5".into())),
+            ("99999999999", PriceError::Format("99999999999".into())),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_price(input), Err(expected), "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn parses_age_filters() {
+        let cases = [
+            ("0+", AgeFilter::UpTo(0)),
+            ("12", AgeFilter::UpTo(12)),
+            (" 16 + ", AgeFilter::UpTo(16)),
+            ("Семья", AgeFilter::FAMILY),
+            ("без детских", AgeFilter::NoKids),
+            ("любой", AgeFilter::Any),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_age_filter(input), Ok(expected), "input: {input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_age_filters() {
+        let cases = [
+            ("", AgeError::Empty),
+            ("7+", AgeError::Format("7+".into())),
+            ("детям", AgeError::Format("детям".into())),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_age_filter(input), Err(expected), "input: {input:?}");
+        }
+    }
+
+    fn moscow_now() -> DateTime<chrono_tz::Tz> {
+        // A Wednesday.
+        chrono_tz::Europe::Moscow
+            .with_ymd_and_hms(2026, 10, 14, 15, 0, 0)
+            .unwrap()
+    }
+
+    fn utc(month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, month, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_snooze() {
+        let cases = [
+            ("3d", utc(10, 17, 12)),
+            ("3 д", utc(10, 17, 12)),
+            ("12ч", utc(10, 15, 0)),
+            ("2w", utc(10, 28, 12)),
+            ("неделя", utc(10, 21, 12)),
+            ("месяц", utc(11, 14, 12)),
+            // Weekdays resume at midnight Moscow time, 21:00 UTC the day before.
+            ("понедельник", utc(10, 18, 21)),
+            ("Пн", utc(10, 18, 21)),
+            ("пятницу", utc(10, 15, 21)),
+            ("среда", utc(10, 20, 21)),
+            ("2026-11-01", utc(10, 31, 21)),
+            ("01.11.2026", utc(10, 31, 21)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_snooze(input, moscow_now()),
+                Ok(expected),
+                "input: {input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_snooze() {
+        let cases = [
+            ("", SnoozeError::Empty),
+            ("0d", SnoozeError::NotPositive),
+            ("-3d", SnoozeError::NotPositive),
+            ("9999999999999w", SnoozeError::TooLong),
+            ("400d", SnoozeError::TooLong),
+            ("2030-01-01", SnoozeError::TooLong),
+            ("16.10.2027", SnoozeError::TooLong),
+            ("2026-10-01", SnoozeError::Past),
+            ("3x", SnoozeError::Format("3x".into())),
+            ("d", SnoozeError::Format("d".into())),
+            ("когда-нибудь", SnoozeError::Format("когда-нибудь".into())),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_snooze(input, moscow_now()),
+                Err(expected),
+                "input: {input:?}"
+            );
+        }
+    }
+}
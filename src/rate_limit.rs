@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// Telegram allows about 30 messages per second per bot across all chats; stay a
+/// little below that.
+pub const TELEGRAM_MESSAGES_PER_SECOND: u32 = 25;
+
+/// Spaces out sends that share one budget, such as digests and broadcasts. Clones
+/// share the same budget. Each caller reserves the next free slot, so no window of
+/// one second holds more than `per_second` sends, whoever makes them.
+#[derive(Clone)]
+pub struct RateLimiter {
+    next: Arc<Mutex<Option<Instant>>>,
+    interval: Duration,
+}
+
+impl RateLimiter {
+    pub fn per_second(per_second: u32) -> Self {
+        Self {
+            next: Arc::default(),
+            interval: Duration::from_secs(1) / per_second.max(1),
+        }
+    }
+
+    /// Waits until the caller may send one message.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = next.map_or_else(Instant::now, |next| next.max(Instant::now()));
+            *next = Some(slot + self.interval);
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for the outbox and broadcast workers sending as fast as allowed.
+    async fn send_for(limiter: RateLimiter, sent: Arc<Mutex<Vec<Instant>>>, until: Instant) {
+        while Instant::now() < until {
+            limiter.acquire().await;
+            sent.lock().unwrap().push(Instant::now());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_by_two_senders() {
+        let limiter = RateLimiter::per_second(TELEGRAM_MESSAGES_PER_SECOND);
+        let until = Instant::now() + Duration::from_secs(5);
+        let (outbox, broadcast) = (Arc::default(), Arc::default());
+        tokio::join!(
+            send_for(limiter.clone(), Arc::clone(&outbox), until),
+            send_for(limiter.clone(), Arc::clone(&broadcast), until),
+        );
+
+        let (outbox, broadcast) = (outbox.lock().unwrap(), broadcast.lock().unwrap());
+        assert!(!outbox.is_empty() && !broadcast.is_empty());
+        let mut sent: Vec<Instant> = outbox.iter().chain(broadcast.iter()).copied().collect();
+        sent.sort();
+        let limit = TELEGRAM_MESSAGES_PER_SECOND as usize;
+        for window in sent.windows(limit + 1) {
+            assert!(
+                window[limit] - window[0] >= Duration::from_secs(1),
+                "{} messages within {:?}",
+                limit + 1,
+                window[limit] - window[0]
+            );
+        }
+        assert!(sent.len() >= 5 * limit);
+    }
+}
@@ -0,0 +1,457 @@
+use std::{collections::HashSet, future::Future};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use tokio::time::{interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+use crate::{db::User, metrics::metrics};
+
+/// How late a digest may still go out, e.g. after a restart.
+pub const MISSED_SLOT_GRACE_HOURS: i64 = 2;
+
+/// Source of the current time, so the scheduler can be driven by tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// When `time` on `date` in `timezone` happens. A time skipped by a DST jump moves
+/// forward by the jump; a time that happens twice uses the first occurrence.
+pub fn slot(date: NaiveDate, time: NaiveTime, timezone: Tz) -> DateTime<Utc> {
+    let local = date.and_time(time);
+    (0..=3)
+        .find_map(|hours| {
+            (local + Duration::hours(hours))
+                .and_local_timezone(timezone)
+                .earliest()
+        })
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+/// The first slot of `user` that may still be sent at `now`, with its local date:
+/// not yet sent, not inside a pause, and less than [`MISSED_SLOT_GRACE_HOURS`] old.
+/// Older slots are skipped rather than sending a morning digest in the evening.
+pub fn next_slot(
+    user: &User,
+    now: DateTime<Utc>,
+    timezone: Tz,
+) -> Option<(NaiveDate, DateTime<Utc>)> {
+    if !user.active || user.paused {
+        return None;
+    }
+    let after = now - Duration::hours(MISSED_SLOT_GRACE_HOURS);
+    let after = user
+        .last_digest_at
+        .map_or(after, |sent_at| sent_at.max(after));
+    let not_before = user.paused_until.map_or(after, |until| until.max(after));
+    let first = not_before.with_timezone(&timezone).date_naive() - Duration::days(1);
+    (0..=3)
+        .map(|days| first + Duration::days(days))
+        .map(|date| (date, slot(date, user.notification_time, timezone)))
+        .find(|(_, slot)| *slot > after && *slot >= not_before)
+}
+
+/// What `next_fire_at` should hold for `user` at `now`.
+pub fn next_fire_at(user: &User, now: DateTime<Utc>, timezone: Tz) -> Option<DateTime<Utc>> {
+    next_slot(user, now, timezone).map(|(_, slot)| slot)
+}
+
+/// Local date of the digest `user` should get at `now`, if one is due.
+pub fn due_date(user: &User, now: DateTime<Utc>, timezone: Tz) -> Option<NaiveDate> {
+    next_slot(user, now, timezone)
+        .filter(|(_, slot)| *slot <= now)
+        .map(|(date, _)| date)
+}
+
+/// Outcome of one tick: digests to send, and users whose `next_fire_at` is stale.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub due: Vec<(User, NaiveDate)>,
+    pub reschedule: Vec<(u64, Option<DateTime<Utc>>)>,
+}
+
+pub struct Scheduler<C> {
+    clock: C,
+    timezone: Tz,
+    tick: std::time::Duration,
+    /// Digests handed out by this run, so a slow delivery is not started twice.
+    dispatched: HashSet<(u64, NaiveDate)>,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C, timezone: Tz, tick: std::time::Duration) -> Self {
+        Self {
+            clock,
+            timezone,
+            tick,
+            dispatched: HashSet::new(),
+        }
+    }
+
+    /// Sorts `users` loaded for `now` into due digests and users to reschedule. Due
+    /// users keep their `next_fire_at` until the digest is queued.
+    pub fn plan(&mut self, users: Vec<User>, now: DateTime<Utc>) -> Plan {
+        let today = now.with_timezone(&self.timezone).date_naive();
+        self.dispatched
+            .retain(|(_, date)| *date >= today - Duration::days(1));
+        let mut plan = Plan::default();
+        for user in users {
+            match next_slot(&user, now, self.timezone) {
+                Some((date, slot)) if slot <= now => {
+                    if self.dispatched.insert((user.tg_id, date)) {
+                        plan.due.push((user, date));
+                    }
+                }
+                next => {
+                    let next = next.map(|(_, slot)| slot);
+                    if next != user.next_fire_at {
+                        plan.reschedule.push((user.tg_id, next));
+                    }
+                }
+            }
+        }
+        plan
+    }
+
+    /// Every tick, loads the users `next_fire_at` points at with `load`, stores new
+    /// fire times with `reschedule` and passes due digests to `deliver`, until
+    /// `shutdown`. Ticks that run late are delayed rather than bunched up.
+    pub async fn run<L, LF, R, RF, D, DF>(
+        &mut self,
+        shutdown: &CancellationToken,
+        mut load: L,
+        mut reschedule: R,
+        mut deliver: D,
+    ) where
+        L: FnMut(DateTime<Utc>) -> LF,
+        LF: Future<Output = Vec<User>>,
+        R: FnMut(Vec<(u64, Option<DateTime<Utc>>)>) -> RF,
+        RF: Future<Output = ()>,
+        D: FnMut(User, NaiveDate) -> DF,
+        DF: Future<Output = ()>,
+    {
+        let mut interval = interval(self.tick);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            metrics().tick();
+            let now = self.clock.now();
+            let users = load(now).await;
+            let loaded = users.len();
+            let plan = self.plan(users, now);
+            tracing::debug!(
+                users = loaded,
+                due = plan.due.len(),
+                rescheduled = plan.reschedule.len(),
+                "scheduler tick"
+            );
+            if !plan.reschedule.is_empty() {
+                reschedule(plan.reschedule).await;
+            }
+            for (user, date) in plan.due {
+                if shutdown.is_cancelled() {
+                    break;
+                }
+                deliver(user, date).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use chrono::TimeZone;
+    use tokio::time::{sleep, Instant};
+
+    use super::*;
+
+    /// Follows tokio's paused clock, starting at `start`.
+    struct TestClock {
+        start: DateTime<Utc>,
+        origin: Instant,
+    }
+
+    impl TestClock {
+        fn new(start: DateTime<Utc>) -> Self {
+            Self {
+                start,
+                origin: Instant::now(),
+            }
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.start + Duration::from_std(self.origin.elapsed()).unwrap()
+        }
+    }
+
+    impl Clock for Arc<TestClock> {
+        fn now(&self) -> DateTime<Utc> {
+            self.as_ref().now()
+        }
+    }
+
+    type Sent = Arc<Mutex<HashMap<u64, Vec<NaiveDate>>>>;
+
+    fn user(tg_id: u64, hour: u32, minute: u32) -> User {
+        User {
+            tg_id,
+            notification_time: NaiveTime::from_hms_opt(hour, minute, 0).unwrap(),
+            ..User::default()
+        }
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn dates(from: NaiveDate, days: i64) -> Vec<NaiveDate> {
+        (0..days).map(|day| from + Duration::days(day)).collect()
+    }
+
+    /// Runs a scheduler over `users` for `duration`, recording delivered digests in
+    /// `sent`. Loading and updating users mirrors `get_due_users`, `set_next_fire_at`
+    /// and `set_last_digest_at`.
+    async fn run_for(
+        users: &Arc<Mutex<Vec<User>>>,
+        sent: &Sent,
+        start: DateTime<Utc>,
+        timezone: Tz,
+        tick: std::time::Duration,
+        duration: std::time::Duration,
+    ) {
+        let clock = Arc::new(TestClock::new(start));
+        let shutdown = CancellationToken::new();
+        let stop = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                sleep(duration).await;
+                shutdown.cancel();
+            }
+        });
+        let mut scheduler = Scheduler::new(clock.clone(), timezone, tick);
+        scheduler
+            .run(
+                &shutdown,
+                |now| {
+                    let users = users.lock().unwrap().clone();
+                    async move {
+                        users
+                            .into_iter()
+                            .filter(|user| user.active && !user.paused)
+                            .filter(|user| user.next_fire_at.is_none_or(|at| at <= now))
+                            .collect()
+                    }
+                },
+                |schedule| {
+                    for stored in users.lock().unwrap().iter_mut() {
+                        if let Some((_, next)) = schedule.iter().find(|(id, _)| *id == stored.tg_id)
+                        {
+                            stored.next_fire_at = *next;
+                        }
+                    }
+                    async {}
+                },
+                |user, date| {
+                    let now = clock.now();
+                    sent.lock()
+                        .unwrap()
+                        .entry(user.tg_id)
+                        .or_default()
+                        .push(date);
+                    for stored in users.lock().unwrap().iter_mut() {
+                        if stored.tg_id == user.tg_id {
+                            stored.last_digest_at = Some(now);
+                            stored.next_fire_at = next_fire_at(stored, now, timezone);
+                        }
+                    }
+                    async {}
+                },
+            )
+            .await;
+        stop.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_one_digest_per_day() {
+        let users = Arc::new(Mutex::new(vec![
+            user(1, 9, 0),
+            user(2, 0, 0),
+            user(3, 23, 59),
+        ]));
+        let sent = Sent::default();
+        let start = utc(2026, 6, 1, 0, 0);
+        // User 3 already got the digest due at 23:59 the day before.
+        users.lock().unwrap()[2].last_digest_at = Some(start);
+        users.lock().unwrap()[2].next_fire_at = Some(utc(2026, 6, 1, 23, 59));
+        // Stop just before the sixth midnight.
+        let days = std::time::Duration::from_secs(5 * 24 * 60 * 60 - 30);
+        let tick = std::time::Duration::from_secs(60);
+        run_for(&users, &sent, start, chrono_tz::UTC, tick, days).await;
+
+        let sent = sent.lock().unwrap();
+        let june = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+        for tg_id in 1..=3 {
+            assert_eq!(sent[&tg_id], dates(june, 5), "user {tg_id}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_ticks_still_send_once() {
+        let users = Arc::new(Mutex::new(vec![user(1, 9, 0), user(2, 9, 3)]));
+        let sent = Sent::default();
+        let start = utc(2026, 6, 1, 0, 0);
+        let days = std::time::Duration::from_secs(3 * 24 * 60 * 60);
+        // Never lands on 09:00 or 09:03 exactly.
+        let tick = std::time::Duration::from_secs(7 * 60 + 13);
+        run_for(&users, &sent, start, chrono_tz::UTC, tick, days).await;
+
+        let sent = sent.lock().unwrap();
+        let june = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+        assert_eq!(sent[&1], dates(june, 3));
+        assert_eq!(sent[&2], dates(june, 3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_do_not_repeat_digests() {
+        let users = Arc::new(Mutex::new(vec![user(1, 9, 0)]));
+        let sent = Sent::default();
+        let tick = std::time::Duration::from_secs(60);
+        let hours = |hours: u64| std::time::Duration::from_secs(hours * 60 * 60);
+        // Stop right after the digest went out and start again a minute later.
+        run_for(
+            &users,
+            &sent,
+            utc(2026, 6, 1, 8, 0),
+            chrono_tz::UTC,
+            tick,
+            hours(1) + tick * 2,
+        )
+        .await;
+        run_for(
+            &users,
+            &sent,
+            utc(2026, 6, 1, 9, 3),
+            chrono_tz::UTC,
+            tick,
+            hours(25),
+        )
+        .await;
+        // Down over the next slot for longer than the grace period.
+        run_for(
+            &users,
+            &sent,
+            utc(2026, 6, 3, 12, 0),
+            chrono_tz::UTC,
+            tick,
+            hours(24),
+        )
+        .await;
+
+        let june = |day| NaiveDate::from_ymd_opt(2026, 6, day).unwrap();
+        assert_eq!(sent.lock().unwrap()[&1], vec![june(1), june(2), june(4)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_within_grace_catches_up() {
+        let users = Arc::new(Mutex::new(vec![user(1, 9, 0)]));
+        let sent = Sent::default();
+        let tick = std::time::Duration::from_secs(60);
+        let start = utc(2026, 6, 1, 10, 30);
+        run_for(&users, &sent, start, chrono_tz::UTC, tick, tick * 3).await;
+
+        let june = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+        assert_eq!(sent.lock().unwrap()[&1], vec![june]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dst_transitions_send_once() {
+        let berlin = chrono_tz::Europe::Berlin;
+        // 02:30 doesn't exist on 2026-03-29 and happens twice on 2026-10-25.
+        let users = Arc::new(Mutex::new(vec![user(1, 2, 30), user(2, 9, 0)]));
+        let tick = std::time::Duration::from_secs(60);
+        let days = std::time::Duration::from_secs(3 * 24 * 60 * 60);
+        for (day, month) in [(28, 3), (24, 10)] {
+            let sent = Sent::default();
+            for user in users.lock().unwrap().iter_mut() {
+                user.last_digest_at = None;
+                user.next_fire_at = None;
+            }
+            let start = utc(2026, month, day, 0, 0);
+            run_for(&users, &sent, start, berlin, tick, days).await;
+
+            let first = NaiveDate::from_ymd_opt(2026, month, day).unwrap();
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent[&1], dates(first, 3), "02:30 around {first}");
+            assert_eq!(sent[&2], dates(first, 3), "09:00 around {first}");
+        }
+    }
+
+    #[test]
+    fn skipped_time_moves_forward() {
+        let berlin = chrono_tz::Europe::Berlin;
+        let date = NaiveDate::from_ymd_opt(2026, 3, 29).unwrap();
+        let time = NaiveTime::from_hms_opt(2, 30, 0).unwrap();
+        assert_eq!(slot(date, time, berlin), utc(2026, 3, 29, 1, 30));
+        let date = NaiveDate::from_ymd_opt(2026, 10, 25).unwrap();
+        assert_eq!(slot(date, time, berlin), utc(2026, 10, 25, 0, 30));
+    }
+
+    #[test]
+    fn paused_and_inactive_users_are_not_due() {
+        let now = utc(2026, 6, 1, 9, 30);
+        let mut paused = user(1, 9, 0);
+        paused.paused = true;
+        let mut inactive = user(2, 9, 0);
+        inactive.active = false;
+        let mut snoozed = user(3, 9, 0);
+        snoozed.paused_until = Some(now + Duration::days(1));
+        for user in [paused, inactive, snoozed] {
+            assert_eq!(due_date(&user, now, chrono_tz::UTC), None);
+        }
+        assert!(due_date(&user(4, 9, 0), now, chrono_tz::UTC).is_some());
+    }
+
+    #[test]
+    fn plan_reschedules_missed_and_unscheduled_users() {
+        let now = utc(2026, 6, 1, 12, 0);
+        let mut missed = user(1, 9, 0);
+        missed.next_fire_at = Some(utc(2026, 6, 1, 9, 0));
+        let unscheduled = user(2, 18, 0);
+        let due = user(3, 11, 0);
+        let mut scheduler = Scheduler::new(SystemClock, chrono_tz::UTC, Default::default());
+        let plan = scheduler.plan(vec![missed, unscheduled, due], now);
+
+        assert_eq!(plan.due.len(), 1);
+        assert_eq!(plan.due[0].0.tg_id, 3);
+        assert_eq!(
+            plan.reschedule,
+            vec![
+                (1, Some(utc(2026, 6, 2, 9, 0))),
+                (2, Some(utc(2026, 6, 1, 18, 0))),
+            ]
+        );
+        // Already handed out, so not due again until it is sent.
+        let due = user(3, 11, 0);
+        assert!(scheduler.plan(vec![due], now).due.is_empty());
+    }
+}